@@ -0,0 +1,215 @@
+use std::char;
+
+/// Character encodings of documents, which are transcoded to UTF-8 when parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// Detected from the byte order mark or the first characters of the document, then from
+    /// the `encoding` of its XML declaration, UTF-8 being the default.
+    #[default]
+    Auto,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Utf32Le,
+    Utf32Be,
+    /// ISO-8859-1, whose characters are the first 256 of Unicode.
+    Latin1,
+}
+
+/// Detects the encoding of `contents` as the XML specification describes it, returning the
+/// offset of the encoding name if the XML declaration names an unsupported one.
+pub(crate) fn detect(contents: &[u8]) -> Result<Encoding, usize> {
+    let encoding = match contents {
+        [0x00, 0x00, 0xFE, 0xFF, ..] | [0x00, 0x00, 0x00, b'<', ..] => Encoding::Utf32Be,
+        [0xFF, 0xFE, 0x00, 0x00, ..] | [b'<', 0x00, 0x00, 0x00, ..] => Encoding::Utf32Le,
+        // no XML document starts with a null byte next to '<' in UTF-8
+        [0xFE, 0xFF, ..] | [0x00, b'<', ..] => Encoding::Utf16Be,
+        [0xFF, 0xFE, ..] | [b'<', 0x00, ..] => Encoding::Utf16Le,
+        [0xEF, 0xBB, 0xBF, ..] => Encoding::Utf8,
+        _ => match declared_encoding(contents) {
+            Some((start, end)) => match &contents[start..end].to_ascii_lowercase()[..] {
+                b"iso-8859-1" | b"iso_8859-1" | b"latin1" | b"l1" => Encoding::Latin1,
+                // the first characters were read as ASCII, which rules out UTF-16 and UTF-32
+                b"utf-8" | b"utf8" | b"us-ascii" | b"ascii" | b"utf-16" | b"utf-32" => {
+                    Encoding::Utf8
+                }
+                _ => return Err(start),
+            },
+            None => Encoding::Utf8,
+        },
+    };
+    Ok(encoding)
+}
+
+/// Whether `contents` is too short for `detect` to tell the encoding of the input it starts.
+pub(crate) fn is_undetermined(contents: &[u8]) -> bool {
+    contents.len() < 4
+        || b"<?xml".starts_with(contents)
+        || (contents.starts_with(b"<?xml") && !contents.windows(2).any(|pair| pair == b"?>"))
+}
+
+/// Returns the range of the `encoding` value of the XML declaration `contents` starts with.
+fn declared_encoding(contents: &[u8]) -> Option<(usize, usize)> {
+    if !contents.starts_with(b"<?xml") {
+        return None;
+    }
+    let end = contents.windows(2).position(|pair| pair == b"?>")?;
+    let declaration = &contents[..end];
+    let mut i = declaration
+        .windows(8)
+        .position(|name| name == b"encoding")?
+        + 8;
+    while declaration.get(i).is_some_and(u8::is_ascii_whitespace) {
+        i += 1;
+    }
+    if declaration.get(i) != Some(&b'=') {
+        return None;
+    }
+    i += 1;
+    while declaration.get(i).is_some_and(u8::is_ascii_whitespace) {
+        i += 1;
+    }
+    let quote = *declaration.get(i).filter(|&&c| c == b'"' || c == b'\'')?;
+    let start = i + 1;
+    let length = declaration[start..].iter().position(|&c| c == quote)?;
+    Some((start, start + length))
+}
+
+/// Transcodes `contents` from `encoding`, which is neither `Auto` nor `Utf8`, to UTF-8,
+/// returning the offset of the first malformed character if there is one.
+pub(crate) fn transcode(contents: &[u8], encoding: Encoding) -> Result<String, usize> {
+    let mut result = String::with_capacity(contents.len());
+    match encoding {
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let unit = |i: usize| {
+                let pair = [contents[i], contents[i + 1]];
+                if encoding == Encoding::Utf16Le {
+                    u16::from_le_bytes(pair)
+                } else {
+                    u16::from_be_bytes(pair)
+                }
+            };
+            let mut i = 0;
+            while i < contents.len() {
+                if i + 2 > contents.len() {
+                    return Err(i);
+                }
+                let high = unit(i);
+                let (code, length) = match high {
+                    0xD800..=0xDBFF if i + 4 <= contents.len() => {
+                        let low = unit(i + 2);
+                        if !(0xDC00..=0xDFFF).contains(&low) {
+                            return Err(i);
+                        }
+                        let code = 0x10000 + ((high as u32 - 0xD800) << 10) + (low as u32 - 0xDC00);
+                        (code, 4)
+                    }
+                    _ => (high as u32, 2),
+                };
+                result.push(char::from_u32(code).ok_or(i)?);
+                i += length;
+            }
+        }
+        Encoding::Utf32Le | Encoding::Utf32Be => {
+            for (n, bytes) in contents.chunks(4).enumerate() {
+                let bytes = [
+                    bytes[0],
+                    *bytes.get(1).ok_or(n * 4)?,
+                    *bytes.get(2).ok_or(n * 4)?,
+                    *bytes.get(3).ok_or(n * 4)?,
+                ];
+                let code = if encoding == Encoding::Utf32Le {
+                    u32::from_le_bytes(bytes)
+                } else {
+                    u32::from_be_bytes(bytes)
+                };
+                result.push(char::from_u32(code).ok_or(n * 4)?);
+            }
+        }
+        Encoding::Latin1 => result.extend(contents.iter().map(|&c| c as char)),
+        Encoding::Auto | Encoding::Utf8 => unreachable!("UTF-8 is not transcoded"),
+    }
+    Ok(result)
+}
+
+/// Maps `offset` in `utf8`, the transcoding of `contents` from `encoding`, back to an offset
+/// in `contents`.
+pub(crate) fn input_offset(
+    contents: &[u8],
+    encoding: Encoding,
+    utf8: &str,
+    offset: usize,
+) -> usize {
+    let characters = utf8.as_bytes()[..offset.min(utf8.len())]
+        .iter()
+        .filter(|&&c| c & 0xC0 != 0x80);
+    match encoding {
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            // characters above U+FFFF take a surrogate pair
+            let (mut i, mut characters) = (0, characters.count());
+            while characters > 0 && i + 2 <= contents.len() {
+                let high = if encoding == Encoding::Utf16Le {
+                    contents[i + 1]
+                } else {
+                    contents[i]
+                };
+                i += if (0xD8..=0xDB).contains(&high) { 4 } else { 2 };
+                characters -= 1;
+            }
+            i
+        }
+        Encoding::Utf32Le | Encoding::Utf32Be => characters.count() * 4,
+        Encoding::Latin1 | Encoding::Auto | Encoding::Utf8 => characters.count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_test() {
+        assert_eq!(detect(b"<a/>"), Ok(Encoding::Utf8));
+        assert_eq!(detect(b"\xEF\xBB\xBF<a/>"), Ok(Encoding::Utf8));
+        assert_eq!(detect(b"\xFF\xFE<\x00"), Ok(Encoding::Utf16Le));
+        assert_eq!(detect(b"\x00<\x00?"), Ok(Encoding::Utf16Be));
+        assert_eq!(detect(b"<\x00a\x00"), Ok(Encoding::Utf16Le));
+        assert_eq!(
+            detect(b"\xFF\xFE\x00\x00<\x00\x00\x00"),
+            Ok(Encoding::Utf32Le)
+        );
+        assert_eq!(detect(b"\x00\x00\x00<"), Ok(Encoding::Utf32Be));
+        assert_eq!(
+            detect(b"<?xml version='1.0' encoding = \"ISO-8859-1\"?><a/>"),
+            Ok(Encoding::Latin1)
+        );
+        assert_eq!(
+            detect(b"<?xml version='1.0' encoding='utf-8'?>"),
+            Ok(Encoding::Utf8)
+        );
+        assert_eq!(detect(b"<?xml version='1.0' encoding='EBCDIC'?>"), Err(30));
+    }
+
+    #[test]
+    fn transcode_test() {
+        let utf16: Vec<u8> = "<a>\u{e9}\u{1F600}</a>"
+            .encode_utf16()
+            .flat_map(|unit| unit.to_be_bytes().to_vec())
+            .collect();
+        let utf8 = transcode(&utf16, Encoding::Utf16Be).unwrap();
+        assert_eq!(utf8, "<a>\u{e9}\u{1F600}</a>");
+        assert_eq!(input_offset(&utf16, Encoding::Utf16Be, &utf8, 9), 12);
+        assert_eq!(
+            transcode(&utf16[..utf16.len() - 1], Encoding::Utf16Be),
+            Err(18)
+        );
+        assert_eq!(transcode(b"\x00\xD8<\x00", Encoding::Utf16Le), Err(0));
+
+        assert_eq!(transcode(b"<\x00\x00\x00", Encoding::Utf32Le).unwrap(), "<");
+        assert_eq!(transcode(b"\x00\x11\x00\x00", Encoding::Utf32Be), Err(0));
+        assert_eq!(
+            transcode(b"caf\xE9", Encoding::Latin1).unwrap(),
+            "caf\u{e9}"
+        );
+    }
+}
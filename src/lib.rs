@@ -1,5 +1,8 @@
+pub mod encoding;
 pub mod node;
 pub mod parser;
+pub mod resolver;
+pub mod sax;
 pub mod writer;
 
 #[cfg(test)]
@@ -2,14 +2,14 @@ use std::ptr;
 use std::borrow::{Borrow, BorrowMut, Cow};
 
 #[derive(Debug)]
-pub struct Attribute {
-    name: String,
-    value: String,
-    next: Option<Box<Attribute>>,
-    prev: *mut Attribute,
+pub struct Attribute<'a> {
+    name: Cow<'a, str>,
+    value: Cow<'a, str>,
+    next: Option<Box<Attribute<'a>>>,
+    prev: *mut Attribute<'a>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NodeType {
     Element,
     PcData,                //<node> text1 <child/> text2 </node>
@@ -18,6 +18,7 @@ pub enum NodeType {
     ProcessingInstruction, //?name value?>
     Declaration,           //<?xml version="1.0"?>
     Doctype,               //<!DOCTYPE greeting [ <!ELEMENT greeting (#PCDATA)> ]>
+    Document,              //the document itself, parent of the root and the nodes around it
 }
 
 #[derive(Debug)]
@@ -30,13 +31,13 @@ pub struct Node<'a> {
     prev: *mut Node<'a>,
     first_child: Option<Box<Node<'a>>>,
     last_child: *mut Node<'a>,
-    first_attr: Option<Box<Attribute>>,
-    last_attr: *mut Attribute,
+    first_attr: Option<Box<Attribute<'a>>>,
+    last_attr: *mut Attribute<'a>,
 }
 
-impl Attribute {
+impl<'a> Attribute<'a> {
     #[inline]
-    pub fn new<S: Into<String>>(name: S, value: S) -> Box<Self> {
+    pub fn new<S: Into<Cow<'a, str>>>(name: S, value: S) -> Box<Self> {
         Box::new(Attribute {
             name: name.into(),
             value: value.into(),
@@ -56,13 +57,13 @@ impl Attribute {
     }
 
     #[inline]
-    pub fn set_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+    pub fn set_name<S: Into<Cow<'a, str>>>(&mut self, name: S) -> &mut Self {
         self.name = name.into();
         self
     }
 
     #[inline]
-    pub fn set_value<S: Into<String>>(&mut self, value: S) -> &mut Self {
+    pub fn set_value<S: Into<Cow<'a, str>>>(&mut self, value: S) -> &mut Self {
         self.value = value.into();
         self
     }
@@ -79,7 +80,7 @@ impl Attribute {
 
     #[inline]
     pub fn previous_attribute(&self) -> Option<&Self> {
-        unsafe { self.prev.as_ref().map(|attr| attr.borrow()) }
+        unsafe { self.prev.as_ref() }
     }
 
     #[inline]
@@ -88,9 +89,9 @@ impl Attribute {
     }
 }
 
-impl PartialEq for Attribute {
+impl<'a> PartialEq for Attribute<'a> {
     #[inline]
-    fn eq(&self, other: &Attribute) -> bool {
+    fn eq(&self, other: &Attribute<'a>) -> bool {
         self.name == other.name && self.value == other.value
     }
 }
@@ -99,9 +100,9 @@ const EMPTY_STRING: Cow<str> = Cow::Borrowed("");
 
 impl<'a> Node<'a> {
     #[inline]
-    pub fn new<S: Into<String>>(name: S) -> Box<Self> {
+    pub fn new<S: Into<Cow<'a, str>>>(name: S) -> Box<Self> {
         Box::new(Node {
-            name: Cow::Owned(name.into()),
+            name: name.into(),
             node_type: NodeType::Element,
             value: EMPTY_STRING,
             next: None,
@@ -118,7 +119,7 @@ impl<'a> Node<'a> {
     pub fn new_by_type(node_type: NodeType) -> Box<Self> {
         Box::new(Node {
             name: EMPTY_STRING,
-            node_type: node_type,
+            node_type,
             value: EMPTY_STRING,
             next: None,
             prev: ptr::null_mut(),
@@ -136,8 +137,8 @@ impl<'a> Node<'a> {
     }
 
     #[inline]
-    pub fn set_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
-        self.name = Cow::Owned(name.into());
+    pub fn set_name<S: Into<Cow<'a, str>>>(&mut self, name: S) -> &mut Self {
+        self.name = name.into();
         self
     }
 
@@ -147,8 +148,8 @@ impl<'a> Node<'a> {
     }
 
     #[inline]
-    pub fn set_value<S: Into<String>>(&mut self, value: S) -> &mut Self {
-        self.value = Cow::Owned(value.into());
+    pub fn set_value<S: Into<Cow<'a, str>>>(&mut self, value: S) -> &mut Self {
+        self.value = value.into();
         self
     }
 
@@ -175,7 +176,7 @@ impl<'a> Node<'a> {
 
     #[inline]
     pub fn previous_sibling(&self) -> Option<&Self> {
-        unsafe { self.prev.as_ref().map(|node| node.borrow()) }
+        unsafe { self.prev.as_ref() }
     }
 
     #[inline]
@@ -185,7 +186,7 @@ impl<'a> Node<'a> {
 
     #[inline]
     pub fn parent(&self) -> Option<&Self> {
-        unsafe { self.parent.as_ref().map(|node| node.borrow()) }
+        unsafe { self.parent.as_ref() }
     }
 
     #[inline]
@@ -205,7 +206,7 @@ impl<'a> Node<'a> {
 
     #[inline]
     pub fn last_child(&self) -> Option<&Self> {
-        unsafe { self.last_child.as_ref().map(|node| node.borrow()) }
+        unsafe { self.last_child.as_ref() }
     }
 
     #[inline]
@@ -214,28 +215,28 @@ impl<'a> Node<'a> {
     }
 
     #[inline]
-    pub fn first_attribute(&self) -> Option<&Attribute> {
+    pub fn first_attribute(&self) -> Option<&Attribute<'a>> {
         self.first_attr.as_ref().map(|node| node.borrow())
     }
 
     #[inline]
-    pub fn first_attribute_mut(&mut self) -> Option<&mut Attribute> {
+    pub fn first_attribute_mut(&mut self) -> Option<&mut Attribute<'a>> {
         self.first_attr.as_mut().map(|node| node.borrow_mut())
     }
 
     #[inline]
-    pub fn last_attribute(&self) -> Option<&Attribute> {
-        unsafe { self.last_attr.as_ref().map(|node| node.borrow()) }
+    pub fn last_attribute(&self) -> Option<&Attribute<'a>> {
+        unsafe { self.last_attr.as_ref() }
     }
 
     #[inline]
-    pub fn last_attribute_mut(&mut self) -> Option<&mut Attribute> {
+    pub fn last_attribute_mut(&mut self) -> Option<&mut Attribute<'a>> {
         unsafe { self.last_attr.as_mut().map(|node| node.borrow_mut()) }
     }
 
     #[inline]
-    pub fn attribute<S: Into<String>>(&self, attr_name: S) -> Option<&str> {
-        let target = attr_name.into();
+    pub fn attribute<S: AsRef<str>>(&self, attr_name: S) -> Option<&str> {
+        let target = attr_name.as_ref();
         let mut attr_option = self.first_attribute();
         let mut result: Option<&str> = None;
         while attr_option.is_some() && result.is_none() {
@@ -249,8 +250,8 @@ impl<'a> Node<'a> {
     }
 
     #[inline]
-    pub fn append_child<S: Into<String>>(&mut self, name: S) -> &mut Self {
-        let mut node = Node::new(name.into());
+    pub fn append_child<S: Into<Cow<'a, str>>>(&mut self, name: S) -> &mut Self {
+        let mut node = Node::new(name);
         let raw_ptr: *mut _ = &mut *node;
         node.parent = self;
         match unsafe { self.last_child.as_mut() } {
@@ -268,8 +269,8 @@ impl<'a> Node<'a> {
     }
 
     #[inline]
-    pub fn prepend_child<S: Into<String>>(&mut self, name: S) -> &mut Self {
-        let mut node = Node::new(name.into());
+    pub fn prepend_child<S: Into<Cow<'a, str>>>(&mut self, name: S) -> &mut Self {
+        let mut node = Node::new(name);
         let raw_ptr: *mut _ = &mut *node;
         node.parent = self;
         match self.first_child.take() {
@@ -325,22 +326,32 @@ impl<'a> Node<'a> {
     }
 
     #[inline]
-    pub fn insert_child_after<S: Into<String>>(&mut self, name: S, child: &mut Self) -> &mut Self {
-        let mut node = Node::new(name.into());
+    pub fn insert_child_after<S: Into<Cow<'a, str>>>(
+        &mut self,
+        name: S,
+        child: &mut Self,
+    ) -> &mut Self {
+        let mut node = Node::new(name);
         let raw_ptr: *mut _ = &mut *node;
         node.parent = self;
 
         node.prev = child;
         node.next = child.next.take();
-        node.next.as_mut().map(|n| n.prev = raw_ptr);
+        if let Some(next) = node.next.as_mut() {
+            next.prev = raw_ptr;
+        }
         child.next = Some(node);
 
         unsafe { &mut *raw_ptr }
     }
 
     #[inline]
-    pub fn insert_child_before<S: Into<String>>(&mut self, name: S, child: &mut Self) -> &mut Self {
-        let mut node = Node::new(name.into());
+    pub fn insert_child_before<S: Into<Cow<'a, str>>>(
+        &mut self,
+        name: S,
+        child: &mut Self,
+    ) -> &mut Self {
+        let mut node = Node::new(name);
         let raw_ptr: *mut _ = &mut *node;
         node.parent = self;
 
@@ -357,7 +368,11 @@ impl<'a> Node<'a> {
     }
 
     #[inline]
-    pub fn append_attribute<S: Into<String>>(&mut self, name: S, value: S) -> &mut Attribute {
+    pub fn append_attribute<S: Into<Cow<'a, str>>>(
+        &mut self,
+        name: S,
+        value: S,
+    ) -> &mut Attribute<'a> {
         let mut attr = Attribute::new(name, value);
         let raw_ptr: *mut _ = &mut *attr;
         match unsafe { self.last_attr.as_mut() } {
@@ -375,7 +390,11 @@ impl<'a> Node<'a> {
     }
 
     #[inline]
-    pub fn prepend_attribute<S: Into<String>>(&mut self, name: S, value: S) -> &mut Attribute {
+    pub fn prepend_attribute<S: Into<Cow<'a, str>>>(
+        &mut self,
+        name: S,
+        value: S,
+    ) -> &mut Attribute<'a> {
         let mut attr = Attribute::new(name, value);
         let raw_ptr: *mut _ = &mut *attr;
         match self.first_attr.take() {
@@ -392,6 +411,58 @@ impl<'a> Node<'a> {
         unsafe { &mut *raw_ptr }
     }
 
+    /// Sets the value of the attribute named `name`, appending the attribute if the node has
+    /// none with that name.
+    #[inline]
+    pub fn set_attribute<S: Into<Cow<'a, str>>>(
+        &mut self,
+        name: S,
+        value: S,
+    ) -> &mut Attribute<'a> {
+        let name = name.into();
+        let mut attr_ptr = self
+            .first_attribute_mut()
+            .map_or(ptr::null_mut(), |attr| attr as *mut Attribute<'a>);
+        while let Some(attr) = unsafe { attr_ptr.as_mut() } {
+            if attr.name == name {
+                return attr.set_value(value);
+            }
+            attr_ptr = attr
+                .next_attribute_mut()
+                .map_or(ptr::null_mut(), |attr| attr as *mut Attribute<'a>);
+        }
+        self.append_attribute(name, value.into())
+    }
+
+    /// Removes the first attribute named `name`, returning whether there was one.
+    #[inline]
+    pub fn remove_attribute<S: AsRef<str>>(&mut self, name: S) -> bool {
+        let name = name.as_ref();
+        let mut attr_ptr = self
+            .first_attribute_mut()
+            .map_or(ptr::null_mut(), |attr| attr as *mut Attribute<'a>);
+        while let Some(attr) = unsafe { attr_ptr.as_mut() } {
+            if attr.name == name {
+                let prev = attr.prev;
+                let mut next = attr.next.take();
+                match next.as_mut() {
+                    Some(next) => next.prev = prev,
+                    None => self.last_attr = prev,
+                }
+                // dropping the box of the attribute, which `attr` points to
+                match unsafe { prev.as_mut() } {
+                    Some(prev) => prev.next = next,
+                    None => self.first_attr = next,
+                }
+                return true;
+            }
+            attr_ptr = attr
+                .next_attribute_mut()
+                .map_or(ptr::null_mut(), |attr| attr as *mut Attribute<'a>);
+        }
+        false
+    }
+
     // xml_attribute xml_node::insert_attribute_after(const char_t* name, const xml_attribute& attr);
     // xml_attribute xml_node::insert_attribute_before(const char_t* name, const xml_attribute& attr);
 
@@ -400,6 +471,16 @@ impl<'a> Node<'a> {
     // iterator end() const;
 }
 
+impl<'a> Drop for Node<'a> {
+    fn drop(&mut self) {
+        // one at a time, as dropping the boxes in a chain recursively can overflow the stack
+        let mut attr = self.first_attr.take();
+        while let Some(mut a) = attr {
+            attr = a.next.take();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -462,4 +543,48 @@ mod tests {
             attr.set_value("main");
         }
     }
+
+    #[test]
+    fn set_attribute_test() {
+        let mut node = Node::new("a");
+        node.append_attribute("x", "1");
+        node.append_attribute("y", "2");
+
+        node.set_attribute("x", "3");
+        node.set_attribute("z", "4");
+        assert_eq!(node.attribute("x"), Some("3"));
+        assert_eq!(node.attribute("z"), Some("4"));
+        let mut names = Vec::new();
+        let mut attr = node.first_attribute();
+        while let Some(a) = attr {
+            names.push(a.name());
+            attr = a.next_attribute();
+        }
+        assert_eq!(names, ["x", "y", "z"]);
+    }
+
+    #[test]
+    fn remove_attribute_test() {
+        let mut node = Node::new("a");
+        node.append_attribute("x", "1");
+        node.append_attribute("y", "2");
+        node.append_attribute("z", "3");
+
+        assert!(node.remove_attribute("y"));
+        assert!(!node.remove_attribute("y"));
+        assert_eq!(
+            node.first_attribute()
+                .unwrap()
+                .next_attribute()
+                .unwrap()
+                .name(),
+            "z"
+        );
+        assert!(node.remove_attribute("z"));
+        assert_eq!(node.last_attribute().unwrap().name(), "x");
+        assert!(node.remove_attribute("x"));
+        assert!(node.first_attribute().is_none() && node.last_attribute().is_none());
+        node.append_attribute("w", "4");
+        assert_eq!(node.first_attribute().unwrap().name(), "w");
+    }
 }
@@ -1,16 +1,149 @@
-use node::{Node, NodeType};
-use std::borrow::{Borrow, BorrowMut};
-use std::panic;
+use encoding::{self, Encoding};
+use node::{Attribute, Node, NodeType};
+use resolver::{EntityResolver, NoExternalEntities};
+use sax::{Handler, SaxError};
+use std::borrow::{Borrow, BorrowMut, Cow};
+use std::char;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::{BitAnd, BitOr, BitOrAssign, Not};
+use std::path::Path;
+use std::str;
+use std::sync::Arc;
 
+#[derive(Debug)]
 pub struct Document<'a> {
-    root: Box<Node<'a>>,
+    // the NodeType::Document node, parent of every top-level node
+    node: Box<Node<'a>>,
+    fragment: bool,
 }
 
-#[derive(Debug)]
-pub enum ParseXmlError {
-    InvalidXml,
+/// Flags controlling how a document is parsed, in the spirit of pugixml's `parse_*` options.
+///
+/// Flags are combined with `|`, e.g. `ParseOptions::ESCAPES | ParseOptions::STRICT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ParseOptions(u32);
+
+/// Parses documents with settings that go beyond `ParseOptions`, like the limits on expanding
+/// the entities declared in the document type declaration.
+///
+/// ```
+/// use dummy_xml::parser::{ParseOptions, Parser};
+///
+/// let document = Parser::new()
+///     .options(ParseOptions::FULL)
+///     .max_entity_depth(4)
+///     .parse(b"<!DOCTYPE a [<!ENTITY who 'world'>]><a>hello &who;</a>")
+///     .unwrap();
+/// assert_eq!(document.root().first_child().unwrap().value(), "hello world");
+/// ```
+///
+/// The replacement text of an entity is inserted as text, not parsed as markup: with
+/// `<!ENTITY b "<b>x</b>">`, `&b;` is the text `<b>x</b>`, not a `b` element.
+///
+/// External entities and the external DTD subset are only read through the `EntityResolver`
+/// given to `entity_resolver`; by default nothing outside of the document is accessed.
+#[derive(Clone)]
+pub struct Parser {
+    options: ParseOptions,
+    max_entity_depth: usize,
+    max_entity_expansion: usize,
+    duplicate_attributes: DuplicateAttributes,
+    resolver: Arc<dyn EntityResolver + Send + Sync>,
+    encoding: Encoding,
+}
+
+/// Which attribute is kept when an element has several with the same name, outside of strict
+/// mode where this is an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateAttributes {
+    /// Keeps the first attribute, ignoring the following ones.
+    KeepFirst,
+    /// Keeps the value of the last attribute, at the position of the first one.
+    KeepLast,
+}
+
+/// The kind of problem that made a document fail to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The input ended in the middle of the document.
+    UnexpectedEof,
+    /// A start tag is malformed, e.g. `<a/ >`.
+    BadStartTag,
+    /// A closing tag is malformed, e.g. `</a b>`.
+    BadEndTag,
+    /// A closing tag does not match the element it is supposed to close.
+    MismatchedCloseTag,
+    /// A closing tag was found while no element is open.
+    UnexpectedCloseTag,
+    /// The input ended while an element was still open.
+    UnclosedElement,
+    /// An attribute is not written as `name="value"` or `name='value'`.
+    BadAttribute,
+    /// An element has two attributes with the same name, e.g. `<a x="1" x="2">`. Only reported
+    /// in strict mode, otherwise `Parser::duplicate_attributes` decides which one is kept.
+    DuplicateAttribute,
+    /// A tag or attribute name starts with, or contains, a character not allowed in names.
+    InvalidName,
+    /// The input contains no element.
+    NoRootElement,
+    /// An element follows the root element, e.g. `<a/><b/>`.
+    MultipleRoots,
+    /// Text other than whitespace is outside of the root element, e.g. `<a/>x`. Only reported
+    /// in strict mode, otherwise it is ignored.
+    TextOutsideRoot,
+    /// A comment contains `--`, or ends with `--->`.
+    BadComment,
+    /// A processing instruction has no valid target, e.g. `<? x?>` or `<?1x?>`.
+    BadPI,
+    /// The XML declaration is malformed, e.g. `<?xml version=1.0?>`.
+    BadDeclaration,
+    /// The XML declaration is not at the very start of the document.
+    MisplacedDeclaration,
+    /// The document type declaration is malformed, or is not before the root element.
+    BadDoctype,
+    /// A name or a value is not valid UTF-8.
+    InvalidUtf8,
+    /// A character or entity reference is malformed, e.g. `&amp` or `&#xZZ;`.
+    BadReference,
+    /// A reference names an entity that is not defined, e.g. `&nbsp;`.
+    UndefinedEntity,
+    /// Expanding entities went deeper, or produced more text, than the `Parser` allows.
+    EntityExpansionLimit,
+    /// An entity references itself, directly or through other entities, e.g.
+    /// `<!ENTITY a "&b;"><!ENTITY b "&a;">`.
+    RecursiveEntity,
+    /// A reference names an external entity that the `EntityResolver` did not provide.
+    UnresolvedEntity,
+    /// An attribute value references an external entity, which XML does not allow. Only
+    /// reported in strict mode, otherwise the reference is left as written.
+    ExternalEntityInAttribute,
+    /// The input is not valid in its encoding, e.g. UTF-16 with an unpaired surrogate.
+    BadEncoding,
+    /// The encoding of the input cannot be read: the XML declaration names an unknown one,
+    /// only reported in strict mode as the document is otherwise read as UTF-8, or a `Reader`
+    /// or a `PushParser` is given input that is not UTF-8.
+    UnsupportedEncoding,
+}
+
+/// Error returned when a document cannot be parsed.
+///
+/// Besides the kind of error it records where the problem was found, both as a
+/// byte offset into the input and as a 1-based line and column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseXmlError {
+    kind: ErrorKind,
+    offset: usize,
+    line: usize,
+    column: usize,
 }
 
+#[derive(Clone, Copy)]
 enum State {
     Start,
     ReadTag,
@@ -18,26 +151,586 @@ enum State {
     ReadTagClose,
     ReadAttribute,
     ReadContent,
+    ReadComment,
+    ReadCData,
+    ReadPI,
+    ReadDoctype,
     End,
 }
 
-const LESS_THAN: u8 = '<' as u8;
-const GREATER_THAN: u8 = '>' as u8;
-const SLASH: u8 = '/' as u8;
-const EQUAL: u8 = '=' as u8;
-const EXCLAMATION_MARK: u8 = '!' as u8;
-const QUESTION_MARK: u8 = '?' as u8;
+/// Where the scan for the '>' ending a start tag or a document type declaration is, which is
+/// all it takes to carry it on from there.
+#[derive(Clone, Copy)]
+enum Markup {
+    Outside,
+    // in a value between the given quotes, in the internal subset or not
+    Quoted(u8, bool),
+    InternalSubset,
+    Comment,
+    PI,
+}
+
+const LESS_THAN: u8 = b'<';
+const GREATER_THAN: u8 = b'>';
+const SLASH: u8 = b'/';
+const EQUAL: u8 = b'=';
+const EXCLAMATION_MARK: u8 = b'!';
+const QUESTION_MARK: u8 = b'?';
+const QUOTE: u8 = b'"';
+const APOSTROPHE: u8 = b'\'';
+const NEW_LINE: u8 = b'\n';
+const CARRIAGE_RETURN: u8 = b'\r';
+const AMPERSAND: u8 = b'&';
+const SEMICOLON: u8 = b';';
+const HASH: u8 = b'#';
+const DASH: u8 = b'-';
+const PERCENT: u8 = b'%';
+const OPEN_BRACKET: u8 = b'[';
+const CLOSE_BRACKET: u8 = b']';
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Number of attributes from which those of a start tag are looked up by name.
+const INDEXED_ATTRIBUTES: usize = 16;
+
+impl ParseOptions {
+    /// Keeps processing instructions, e.g. `<?xml-stylesheet href="style.css"?>`.
+    pub const PI: ParseOptions = ParseOptions(0x0001);
+    /// Keeps comments, e.g. `<!-- comment -->`.
+    pub const COMMENTS: ParseOptions = ParseOptions(0x0002);
+    /// Keeps CDATA sections, e.g. `<![CDATA[a < b]]>`, as `CData` nodes. Without it their content
+    /// is merged into the surrounding text.
+    pub const CDATA: ParseOptions = ParseOptions(0x0004);
+    /// Keeps text made only of whitespace, like the indentation between two elements. Without
+    /// it such text is dropped.
+    pub const WS_PCDATA: ParseOptions = ParseOptions(0x0008);
+    /// Decodes the predefined entities (`&lt;`, `&gt;`, `&amp;`, `&apos;`, `&quot;`) and
+    /// character references (`&#38;`, `&#x26;`) in text and attribute values.
+    pub const ESCAPES: ParseOptions = ParseOptions(0x0010);
+    /// Turns `\r\n` and lone `\r` into `\n`, as the XML specification asks, in text, CDATA
+    /// sections, comments, processing instructions, attribute values, entity values and the
+    /// document type declaration. A `\r` written as `&#13;` is kept.
+    pub const EOL: ParseOptions = ParseOptions(0x0020);
+    /// Turns tabs and line ends in attribute values into spaces, as the XML specification asks.
+    /// Those written as character references, like `&#10;`, are kept.
+    pub const WCONV_ATTRIBUTE: ParseOptions = ParseOptions(0x0040);
+    /// Like `WCONV_ATTRIBUTE`, but also removes leading and trailing whitespace from attribute
+    /// values and replaces each run of whitespace inside them by a single space.
+    pub const WNORM_ATTRIBUTE: ParseOptions = ParseOptions(0x0080);
+    /// Keeps the XML declaration, e.g. `<?xml version="1.0"?>`.
+    pub const DECLARATION: ParseOptions = ParseOptions(0x0100);
+    /// Keeps the document type declaration, e.g. `<!DOCTYPE note SYSTEM "note.dtd">`.
+    pub const DOCTYPE: ParseOptions = ParseOptions(0x0200);
+    /// Removes leading and trailing whitespace from text.
+    pub const TRIM_PCDATA: ParseOptions = ParseOptions(0x0800);
+    /// Parses a fragment: any sequence of elements, text, comments, CDATA sections and
+    /// processing instructions, possibly empty, which become the children of a
+    /// `NodeType::Document` node returned by `Document::root`.
+    pub const FRAGMENT: ParseOptions = ParseOptions(0x1000);
+    /// Replaces each run of whitespace inside text by a single space. Combined with
+    /// `TRIM_PCDATA` this gives the `collapse` whitespace handling of XML Schema.
+    ///
+    /// None of the whitespace options apply inside an element with `xml:space="preserve"`,
+    /// or inside its descendants, where text is always kept as it is.
+    pub const COLLAPSE_PCDATA: ParseOptions = ParseOptions(0x2_0000);
+    /// Reports problems the parser could otherwise recover from, like a malformed reference or
+    /// `--` inside a comment, as errors instead of keeping the offending text as it is.
+    pub const STRICT: ParseOptions = ParseOptions(0x1_0000);
+
+    /// Builds elements, attributes and text only, nothing is decoded or normalized.
+    pub const MINIMAL: ParseOptions = ParseOptions(0);
+    /// Keeps CDATA sections, decodes references and normalizes line ends and whitespace in
+    /// attribute values.
+    pub const DEFAULT: ParseOptions = ParseOptions(
+        ParseOptions::CDATA.0
+            | ParseOptions::ESCAPES.0
+            | ParseOptions::EOL.0
+            | ParseOptions::WCONV_ATTRIBUTE.0,
+    );
+    /// Like `DEFAULT`, but also keeps comments, processing instructions, the XML declaration
+    /// and the document type declaration.
+    pub const FULL: ParseOptions = ParseOptions(
+        ParseOptions::DEFAULT.0
+            | ParseOptions::PI.0
+            | ParseOptions::COMMENTS.0
+            | ParseOptions::DECLARATION.0
+            | ParseOptions::DOCTYPE.0,
+    );
+
+    /// Options with no flag set.
+    #[inline]
+    pub const fn empty() -> ParseOptions {
+        ParseOptions(0)
+    }
+
+    #[inline]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if all flags of `other` are set.
+    #[inline]
+    pub const fn contains(self, other: ParseOptions) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[inline]
+    pub fn insert(&mut self, other: ParseOptions) -> &mut Self {
+        self.0 |= other.0;
+        self
+    }
+
+    #[inline]
+    pub fn remove(&mut self, other: ParseOptions) -> &mut Self {
+        self.0 &= !other.0;
+        self
+    }
+}
+
+impl Default for ParseOptions {
+    #[inline]
+    fn default() -> ParseOptions {
+        ParseOptions::DEFAULT
+    }
+}
+
+impl BitOr for ParseOptions {
+    type Output = ParseOptions;
+
+    #[inline]
+    fn bitor(self, other: ParseOptions) -> ParseOptions {
+        ParseOptions(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for ParseOptions {
+    #[inline]
+    fn bitor_assign(&mut self, other: ParseOptions) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for ParseOptions {
+    type Output = ParseOptions;
+
+    #[inline]
+    fn bitand(self, other: ParseOptions) -> ParseOptions {
+        ParseOptions(self.0 & other.0)
+    }
+}
+
+impl Not for ParseOptions {
+    type Output = ParseOptions;
+
+    #[inline]
+    fn not(self) -> ParseOptions {
+        ParseOptions(!self.0)
+    }
+}
+
+impl ErrorKind {
+    fn description(self) -> &'static str {
+        match self {
+            ErrorKind::UnexpectedEof => "unexpected end of input",
+            ErrorKind::BadStartTag => "malformed start tag",
+            ErrorKind::BadEndTag => "malformed closing tag",
+            ErrorKind::MismatchedCloseTag => "closing tag does not match the opening tag",
+            ErrorKind::UnexpectedCloseTag => "closing tag without an opening tag",
+            ErrorKind::UnclosedElement => "element is not closed",
+            ErrorKind::BadAttribute => "bad attribute syntax",
+            ErrorKind::DuplicateAttribute => "duplicate attribute",
+            ErrorKind::InvalidName => "invalid name",
+            ErrorKind::NoRootElement => "no root element",
+            ErrorKind::MultipleRoots => "multiple root elements",
+            ErrorKind::TextOutsideRoot => "text outside of the root element",
+            ErrorKind::BadComment => "comment contains '--'",
+            ErrorKind::BadPI => "malformed processing instruction",
+            ErrorKind::BadDeclaration => "malformed XML declaration",
+            ErrorKind::BadDoctype => "malformed document type declaration",
+            ErrorKind::MisplacedDeclaration => {
+                "XML declaration is not at the start of the document"
+            }
+            ErrorKind::InvalidUtf8 => "invalid UTF-8",
+            ErrorKind::BadReference => "malformed character or entity reference",
+            ErrorKind::UndefinedEntity => "undefined entity",
+            ErrorKind::EntityExpansionLimit => "entity expansion limit exceeded",
+            ErrorKind::RecursiveEntity => "recursive entity reference",
+            ErrorKind::UnresolvedEntity => "unresolved external entity",
+            ErrorKind::ExternalEntityInAttribute => "external entity in attribute value",
+            ErrorKind::BadEncoding => "input is not valid in its encoding",
+            ErrorKind::UnsupportedEncoding => "unsupported encoding",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl ParseXmlError {
+    /// Creates an error of `kind` found at byte `offset` of `contents`.
+    ///
+    /// Line and column are computed from `contents`; the column counts characters, not bytes.
+    pub fn new(kind: ErrorKind, contents: &[u8], offset: usize) -> ParseXmlError {
+        let offset = offset.min(contents.len());
+        let line_start = contents[..offset]
+            .iter()
+            .rposition(|&c| c == NEW_LINE)
+            .map_or(0, |p| p + 1);
+        let line = contents[..line_start]
+            .iter()
+            .filter(|&&c| c == NEW_LINE)
+            .count()
+            + 1;
+        // count UTF-8 leading bytes only, so that a multi-byte character is one column
+        let column = contents[line_start..offset]
+            .iter()
+            .filter(|&&c| c & 0xC0 != 0x80)
+            .count()
+            + 1;
+
+        ParseXmlError {
+            kind,
+            offset,
+            line,
+            column,
+        }
+    }
+
+    /// Creates an `UnexpectedEof` error at byte `offset` of `contents` whose line and column
+    /// are left for `Tokenizer::next_token` to compute, as the input to come may make it no
+    /// error at all.
+    fn eof(contents: &[u8], offset: usize) -> ParseXmlError {
+        ParseXmlError {
+            kind: ErrorKind::UnexpectedEof,
+            offset: offset.min(contents.len()),
+            line: 0,
+            column: 0,
+        }
+    }
+
+    #[inline]
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Byte offset into the input where the error was found.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// 1-based line where the error was found.
+    #[inline]
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 1-based column where the error was found.
+    #[inline]
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Maps the offset of an error in `utf8`, the transcoding of `contents`, back to
+    /// `contents`. The line and column hold as they are.
+    fn into_input(mut self, contents: &[u8], encoding: Encoding, utf8: &str) -> ParseXmlError {
+        self.offset = encoding::input_offset(contents, encoding, utf8, self.offset);
+        self
+    }
+}
+
+impl fmt::Display for ParseXmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {} (byte {})",
+            self.kind, self.line, self.column, self.offset
+        )
+    }
+}
+
+impl Error for ParseXmlError {}
+
+/// Error returned when a document cannot be loaded, because reading it failed or because it
+/// cannot be parsed.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse(ParseXmlError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "cannot read document: {}", error),
+            LoadError::Parse(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(error) => Some(error),
+            LoadError::Parse(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> LoadError {
+        LoadError::Io(error)
+    }
+}
+
+impl From<ParseXmlError> for LoadError {
+    fn from(error: ParseXmlError) -> LoadError {
+        LoadError::Parse(error)
+    }
+}
+
+impl Document<'static> {
+    /// Loads the file at `path` with `ParseOptions::DEFAULT`, see `Parser::load_file`.
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Document<'static>, LoadError> {
+        Parser::new().load_file(path)
+    }
+
+    /// Loads what `reader` reads with `ParseOptions::DEFAULT`, see `Parser::load_reader`.
+    pub fn load_reader<R: Read>(reader: R) -> Result<Document<'static>, LoadError> {
+        Parser::new().load_reader(reader)
+    }
+
+    /// Parses `contents` with `ParseOptions::DEFAULT`, see `Parser::load_bytes`.
+    pub fn load_bytes(contents: &[u8]) -> Result<Document<'static>, LoadError> {
+        Parser::new().load_bytes(contents)
+    }
+}
 
 impl<'a> Document<'a> {
+    /// The document element, see `document_element`. For a fragment, which may hold several
+    /// elements, this is the document node instead.
     pub fn root(&self) -> &Node<'a> {
-        self.root.borrow()
+        match self.document_element() {
+            Some(element) if !self.fragment => element,
+            _ => self.node(),
+        }
     }
 
     pub fn root_mut(&mut self) -> &mut Node<'a> {
-        self.root.borrow_mut()
+        if self.fragment || self.document_element().is_none() {
+            return self.node_mut();
+        }
+        self.child_by_type_mut(NodeType::Element)
+            .expect("the document element was just found")
+    }
+
+    /// The `NodeType::Document` node, parent of everything at the top level of the document:
+    /// the XML declaration, the document type declaration, the document element and the
+    /// comments and processing instructions before and after it.
+    pub fn node(&self) -> &Node<'a> {
+        self.node.borrow()
+    }
+
+    pub fn node_mut(&mut self) -> &mut Node<'a> {
+        self.node.borrow_mut()
+    }
+
+    /// Copies the names and values the document borrows from its input, so that it no longer
+    /// depends on it.
+    pub fn into_owned<'b>(self) -> Document<'b> {
+        fn copy<'b>(source: &Node, node: &mut Node<'b>) {
+            node.set_node_type(*source.node_type())
+                .set_name(source.name().to_owned())
+                .set_value(source.value().to_owned());
+            let mut attribute = source.first_attribute();
+            while let Some(source) = attribute {
+                node.append_attribute(source.name().to_owned(), source.value().to_owned());
+                attribute = source.next_attribute();
+            }
+        }
+
+        let mut document = Document {
+            node: Node::new_by_type(NodeType::Document),
+            fragment: self.fragment,
+        };
+        copy(self.node(), document.node_mut());
+        // depth first, without recursing so that deep documents cannot overflow the stack
+        let mut source = self.node().first_child();
+        let mut parent: Option<&mut Node<'b>> = Some(document.node_mut());
+        while let Some(node) = source {
+            // `parent` is the copy of the parent of `node`
+            parent = parent
+                .take()
+                .map(|parent| parent.append_child_by_type(NodeType::Element));
+            if let Some(child) = parent.as_mut() {
+                copy(node, child);
+            }
+            // a node without children is followed by its next sibling, or the next sibling of
+            // its closest ancestor that has one
+            source = node.first_child();
+            let mut done = node;
+            while source.is_none() && *done.node_type() != NodeType::Document {
+                parent = parent.take().and_then(|copy| copy.parent_mut());
+                source = done.next_sibling();
+                done = match done.parent() {
+                    Some(up) => up,
+                    None => break,
+                };
+            }
+        }
+        document
+    }
+
+    /// The element all the other elements are in, e.g. `<note>` in
+    /// `<!-- draft --><note>...</note>`. For a fragment, its first top-level element.
+    pub fn document_element(&self) -> Option<&Node<'a>> {
+        self.child_by_type(NodeType::Element)
+    }
+
+    fn child_by_type(&self, node_type: NodeType) -> Option<&Node<'a>> {
+        let mut child = self.node().first_child();
+        while let Some(node) = child {
+            if *node.node_type() == node_type {
+                return Some(node);
+            }
+            child = node.next_sibling();
+        }
+        None
+    }
+
+    fn child_by_type_mut(&mut self, node_type: NodeType) -> Option<&mut Node<'a>> {
+        let mut child = self.node_mut().first_child_mut();
+        while let Some(node) = child {
+            if *node.node_type() == node_type {
+                return Some(node);
+            }
+            child = node.next_sibling_mut();
+        }
+        None
+    }
+
+    /// The `Declaration` node, e.g. `<?xml version="1.0"?>`, which holds the `version`,
+    /// `encoding` and `standalone` attributes.
+    ///
+    /// The parser only keeps the declaration with `ParseOptions::DECLARATION`.
+    pub fn declaration(&self) -> Option<&Node<'a>> {
+        self.child_by_type(NodeType::Declaration)
+    }
+
+    /// The `version` of the XML declaration, e.g. `1.0`.
+    pub fn version(&self) -> Option<&str> {
+        self.declaration()
+            .and_then(|node| node.attribute("version"))
+    }
+
+    /// The `encoding` of the XML declaration, e.g. `UTF-8`.
+    pub fn encoding(&self) -> Option<&str> {
+        self.declaration()
+            .and_then(|node| node.attribute("encoding"))
+    }
+
+    /// The `standalone` of the XML declaration, `Some(true)` for `yes` and `Some(false)` for
+    /// `no`.
+    pub fn standalone(&self) -> Option<bool> {
+        match self
+            .declaration()
+            .and_then(|node| node.attribute("standalone"))
+        {
+            Some("yes") => Some(true),
+            Some("no") => Some(false),
+            _ => None,
+        }
+    }
+
+    /// The `Doctype` node, e.g. `<!DOCTYPE note SYSTEM "note.dtd">`. Its name is the root element
+    /// name and its value is everything between `<!DOCTYPE` and `>`, internal subset included.
+    ///
+    /// The parser only keeps the document type declaration with `ParseOptions::DOCTYPE`.
+    pub fn doctype(&self) -> Option<&Node<'a>> {
+        self.child_by_type(NodeType::Doctype)
+    }
+
+    /// The root element name given by the document type declaration.
+    pub fn doctype_name(&self) -> Option<&str> {
+        self.doctype().map(|node| node.name())
+    }
+
+    /// The public ID of the document type declaration, e.g. `-//W3C//DTD XHTML 1.0 Strict//EN`.
+    pub fn public_id(&self) -> Option<&str> {
+        self.doctype_literal(|header| header.public_id)
+    }
+
+    /// The system ID of the document type declaration, e.g. `note.dtd`.
+    pub fn system_id(&self) -> Option<&str> {
+        self.doctype_literal(|header| header.system_id)
+    }
+
+    fn doctype_literal<F>(&self, literal: F) -> Option<&str>
+    where
+        F: Fn(DoctypeHeader) -> Option<(usize, usize)>,
+    {
+        let value = self.doctype()?.value();
+        read_doctype_header(value.as_bytes(), 0)
+            .ok()
+            .and_then(literal)
+            .map(|(start, end)| &value[start..end])
+    }
+
+    /// Sets the `version` of the XML declaration, adding the declaration if there is none.
+    pub fn set_version<S: Into<String>>(&mut self, version: S) -> &mut Self {
+        self.set_declaration_attribute(0, version.into())
+    }
+
+    /// Sets the `encoding` of the XML declaration, adding the declaration if there is none.
+    pub fn set_encoding<S: Into<String>>(&mut self, encoding: S) -> &mut Self {
+        self.set_declaration_attribute(1, encoding.into())
+    }
+
+    /// Sets the `standalone` of the XML declaration, adding the declaration if there is none.
+    pub fn set_standalone(&mut self, standalone: bool) -> &mut Self {
+        let value = if standalone { "yes" } else { "no" };
+        self.set_declaration_attribute(2, value.to_owned())
+    }
+
+    /// Rebuilds the attributes of the declaration with the one at `index` of
+    /// `DECLARATION_ATTRIBUTES` set to `value`, so that they stay in the order the specification
+    /// requires. A missing declaration is added as the first node of the document.
+    fn set_declaration_attribute(&mut self, index: usize, value: String) -> &mut Self {
+        let mut values: Vec<Option<String>> = DECLARATION_ATTRIBUTES
+            .iter()
+            .map(|name| {
+                self.declaration()
+                    .and_then(|node| node.attribute(*name))
+                    .map(|value| value.to_owned())
+            })
+            .collect();
+        values[index] = Some(value);
+        if values[0].is_none() {
+            values[0] = Some("1.0".to_owned());
+        }
+
+        let declaration = if self.declaration().is_some() {
+            self.child_by_type_mut(NodeType::Declaration)
+                .expect("the declaration was just found")
+        } else {
+            self.node_mut()
+                .prepend_child_by_type(NodeType::Declaration)
+                .set_name("xml")
+        };
+        for (name, value) in DECLARATION_ATTRIBUTES.iter().zip(values) {
+            declaration.remove_attribute(*name);
+            if let Some(value) = value {
+                declaration.append_attribute(name.to_string(), value);
+            }
+        }
+        self
     }
 }
 
+const DECLARATION_ATTRIBUTES: [&str; 3] = ["version", "encoding", "standalone"];
+
 #[allow(dead_code)]
 #[derive(Clone, Copy)]
 enum Chartype {
@@ -51,8 +744,7 @@ enum Chartype {
     StartSymBol = 128, // Any symbol > 127, a-z, A-Z, _, :
 }
 
-const SPACE_AND_CLOSE_SIGN: u8 = Chartype::Space as u8 | Chartype::ParseCData as u8;
-
+#[rustfmt::skip]
 const CHARTYPE_TABLE: [u8; 256] = [
     55,  0,   0,   0,   0,   0,   0,   0,      0,   12,  12,  0,   0,   63,  0,   0,   // 0-15
     0,   0,   0,   0,   0,   0,   0,   0,      0,   0,   0,   0,   0,   0,   0,   0,   // 16-31
@@ -73,214 +765,2808 @@ const CHARTYPE_TABLE: [u8; 256] = [
     192, 192, 192, 192, 192, 192, 192, 192,    192, 192, 192, 192, 192, 192, 192, 192
 ];
 
-macro_rules! skip_chartype {
-    ($contents: ident, $p: ident, $chartype: expr) => {
-        while CHARTYPE_TABLE[$contents[$p] as usize] & $chartype as u8 > 0 {
-            $p += 1;
+/// Returns the character at `$p`, or returns an `UnexpectedEof` error at the end of the input.
+macro_rules! peek {
+    ($contents: ident, $p: expr) => {
+        match $contents.get($p) {
+            Some(&c) => c,
+            None => return Err(ParseXmlError::eof($contents, $p)),
         }
     };
 }
 
-macro_rules! skip_chartype_safe {
-    ($contents: ident, $p: ident, $size: ident, $chartype: expr) => {
-        while $p < $size && CHARTYPE_TABLE[$contents[$p] as usize] & $chartype as u8 > 0 {
+/// Skips characters of `$chartype`, stopping at the end of the input.
+macro_rules! skip_chartype {
+    ($contents: ident, $p: ident, $chartype: expr) => {
+        while $p < $contents.len() && CHARTYPE_TABLE[$contents[$p] as usize] & $chartype as u8 > 0 {
             $p += 1;
         }
     };
 }
 
+/// Moves `$p` to the next character of `$chartype`, which must exist.
 macro_rules! scan_chartype {
     ($contents: ident, $p: ident, $chartype: expr) => {
-        while CHARTYPE_TABLE[$contents[$p] as usize] & $chartype as u8 == 0 {
+        while CHARTYPE_TABLE[peek!($contents, $p) as usize] & $chartype as u8 == 0 {
             $p += 1;
         }
     };
 }
 
+/// Moves `$p` to the next `$char`, which must exist.
 macro_rules! scan_char {
-    ($contents: ident, $p: ident, $char: ident) => {
-        while $contents[$p] != $char {
-            $p += 1;
+    ($contents: ident, $p: ident, $char: expr) => {
+        match $contents[$p.min($contents.len())..]
+            .iter()
+            .position(|&c| c == $char)
+        {
+            Some(offset) => $p += offset,
+            None => return Err(ParseXmlError::eof($contents, $contents.len())),
         }
     };
 }
 
-pub fn parse_str(contents: &str) -> Result<Document, ParseXmlError> {
+pub fn parse_str(contents: &str) -> Result<Document<'_>, ParseXmlError> {
     parse(contents.as_bytes())
 }
 
-pub fn parse_string(contents: &String) -> Result<Document, ParseXmlError> {
+pub fn parse_string(contents: &String) -> Result<Document<'_>, ParseXmlError> {
     parse(contents.as_bytes())
 }
 
-pub fn parse(contents: &[u8]) -> Result<Document, ParseXmlError> {
-    let mut root: Box<Node> = Node::new("");
+pub fn parse(contents: &[u8]) -> Result<Document<'_>, ParseXmlError> {
+    parse_with_options(contents, ParseOptions::default())
+}
 
-    let result = panic::catch_unwind(move || {
-        parse_internal(contents, root.borrow_mut());
-        root
-    });
+/// Parses a fragment like `<b>bold</b> and <i>italic</i>`, see `ParseOptions::FRAGMENT`.
+pub fn parse_fragment(contents: &[u8]) -> Result<Document<'_>, ParseXmlError> {
+    parse_with_options(contents, ParseOptions::DEFAULT | ParseOptions::FRAGMENT)
+}
 
-    match result {
-        Ok(root) => Ok(Document { root: root }),
-        Err(_) => Err(ParseXmlError::InvalidXml),
-    }
+/// Parses `contents` rewriting it in place, see `Parser::parse_in_place`.
+pub fn parse_in_place(contents: &mut [u8]) -> Result<Document<'_>, ParseXmlError> {
+    Parser::new().parse_in_place(contents)
 }
 
-fn parse_internal(contents: &[u8], root: &mut Node) {
-    let mut current_parent: Option<&mut Node> = Some(root);
-    let mut state = State::Start;
-    let mut i = 0;
-    let size = contents.len();
+pub fn parse_with_options(
+    contents: &[u8],
+    options: ParseOptions,
+) -> Result<Document<'_>, ParseXmlError> {
+    Parser::new().options(options).parse(contents)
+}
 
-    loop {
-        state = match state {
-            State::Start => {
-                scan_char!(contents, i, LESS_THAN);
-                State::ReadTag
-            }
-            State::ReadTag => {
-                i += 1; // skip first '<'
-                if i >= size {
-                    State::End
-                } else {
-                    match contents[i] {
-                        SLASH => {
-                            i += 1;
-                            State::ReadTagClose
-                        }
-                        EXCLAMATION_MARK | QUESTION_MARK => {
-                            scan_char!(contents, i, LESS_THAN);
-                            State::ReadTag
+/// Parses `contents` into the callbacks of `handler`, see `Parser::parse_sax`.
+pub fn parse_sax<H: Handler>(contents: &[u8], handler: &mut H) -> Result<(), SaxError<H::Error>> {
+    Parser::new().parse_sax(contents, handler)
+}
+
+impl Parser {
+    /// A parser with `ParseOptions::DEFAULT`, allowing entities to nest 16 levels deep and to
+    /// expand to 8 MiB of text in total, keeping the first of duplicate attributes and
+    /// detecting the encoding.
+    pub fn new() -> Parser {
+        Parser {
+            options: ParseOptions::DEFAULT,
+            max_entity_depth: 16,
+            max_entity_expansion: 8 << 20,
+            duplicate_attributes: DuplicateAttributes::KeepFirst,
+            resolver: Arc::new(NoExternalEntities),
+            encoding: Encoding::Auto,
+        }
+    }
+
+    pub fn options(&mut self, options: ParseOptions) -> &mut Self {
+        self.options = options;
+        self
+    }
+
+    /// Sets how deep entity references may nest inside entity values, `&a;` referencing `&b;`
+    /// being two levels deep.
+    pub fn max_entity_depth(&mut self, depth: usize) -> &mut Self {
+        self.max_entity_depth = depth;
+        self
+    }
+
+    /// Sets how many bytes of text entity references may expand to, over the whole document.
+    pub fn max_entity_expansion(&mut self, bytes: usize) -> &mut Self {
+        self.max_entity_expansion = bytes;
+        self
+    }
+
+    /// Sets which of duplicate attributes is kept when not in strict mode.
+    pub fn duplicate_attributes(&mut self, policy: DuplicateAttributes) -> &mut Self {
+        self.duplicate_attributes = policy;
+        self
+    }
+
+    /// Sets the resolver providing external entities and the external DTD subset.
+    pub fn entity_resolver<R: EntityResolver + Send + Sync + 'static>(
+        &mut self,
+        resolver: R,
+    ) -> &mut Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Sets the encoding of the input, instead of detecting it.
+    pub fn encoding(&mut self, encoding: Encoding) -> &mut Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Parses `contents`, whose names and values are borrowed by the document when it is
+    /// UTF-8. Other encodings are transcoded, and the document then owns its strings.
+    pub fn parse<'a>(&self, contents: &'a [u8]) -> Result<Document<'a>, ParseXmlError> {
+        match self.input_encoding(contents)? {
+            Encoding::Auto | Encoding::Utf8 => self.parse_utf8(contents),
+            encoding => self.parse_transcoded(contents, encoding),
+        }
+    }
+
+    /// Parses `contents` into the callbacks of `handler`, without building a document.
+    /// Parsing stops at the first error, whether the document is malformed or the handler
+    /// returns one.
+    pub fn parse_sax<H: Handler>(
+        &self,
+        contents: &[u8],
+        handler: &mut H,
+    ) -> Result<(), SaxError<H::Error>> {
+        match self.input_encoding(contents)? {
+            Encoding::Auto | Encoding::Utf8 => self.parse_sax_utf8(contents, handler),
+            encoding => {
+                let utf8 = transcode(contents, encoding)?;
+                self.parse_sax_utf8(utf8.as_bytes(), handler)
+                    .map_err(|error| match error {
+                        SaxError::Parse(error) => {
+                            SaxError::Parse(error.into_input(contents, encoding, &utf8))
                         }
-                        _ => State::ReadTagOpen,
-                    }
-                }
+                        error => error,
+                    })
             }
-            State::ReadTagOpen => {
-                let start = i;
-                scan_chartype!(contents, i, SPACE_AND_CLOSE_SIGN);
+        }
+    }
 
-                let tag_name = String::from_utf8(contents[start..i].to_vec()).unwrap();
-                current_parent = current_parent.take().map(|old_parent| {
-                    if old_parent.name().len() == 0 {
-                        old_parent.set_name(tag_name)
-                    } else {
-                        old_parent.append_child(tag_name)
-                    }
-                });
+    fn parse_sax_utf8<H: Handler>(
+        &self,
+        contents: &[u8],
+        handler: &mut H,
+    ) -> Result<(), SaxError<H::Error>> {
+        handler.start_document().map_err(SaxError::Handler)?;
+        for event in self.utf8_reader(contents) {
+            handle_event(handler, event?).map_err(SaxError::Handler)?;
+        }
+        handler.end_document().map_err(SaxError::Handler)
+    }
 
-                State::ReadAttribute
-            }
-            State::ReadTagClose => {
-                scan_char!(contents, i, GREATER_THAN);
-                current_parent = current_parent
-                    .take()
-                    .and_then(|old_parent| old_parent.parent_mut());
+    /// Parses the file at `path` into a document owning its strings.
+    pub fn load_file<P: AsRef<Path>>(&self, path: P) -> Result<Document<'static>, LoadError> {
+        self.load_reader(File::open(path)?)
+    }
 
-                match contents[i] {
-                    GREATER_THAN => {
-                        i += 1;
-                        skip_chartype_safe!(contents, i, size, Chartype::Space);
-                        State::ReadTag
-                    }
-                    _ => State::ReadContent,
-                }
-            }
-            State::ReadAttribute => {
-                skip_chartype!(contents, i, Chartype::Space);
-                match contents[i] {
-                    SLASH => {
-                        i += 1;
-                        current_parent = current_parent
-                            .take()
-                            .and_then(|old_parent| old_parent.parent_mut());
-                        State::ReadContent
-                    }
-                    GREATER_THAN => State::ReadContent,
-                    _ => {
-                        let start = i;
-                        scan_char!(contents, i, EQUAL);
-                        let end = i;
-                        i += 1; //skip =
-                        let quote = contents[i];
-                        i += 1;
-                        let value_start = i;
-                        scan_char!(contents, i, quote);
-                        let name = String::from_utf8(contents[start..end].to_vec()).unwrap();
-                        let value = String::from_utf8(contents[value_start..i].to_vec()).unwrap();
-                        current_parent
-                            .as_mut()
-                            .map(|node| node.append_attribute(name, value));
-                        i += 1;
-                        State::ReadAttribute
-                    }
-                }
-            }
-            State::ReadContent => {
-                i += 1;
-                skip_chartype!(contents, i, Chartype::Space);
+    /// Reads `reader` to the end and parses what it read into a document owning its strings.
+    pub fn load_reader<R: Read>(&self, mut reader: R) -> Result<Document<'static>, LoadError> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        self.load_bytes(&contents)
+    }
 
-                let start = i;
-                scan_char!(contents, i, LESS_THAN);
-                if i > start {
-                    current_parent = current_parent.take().map(|node| {
-                        let txt = String::from_utf8(contents[start..i].to_vec()).unwrap();
-                        node.append_child_by_type(NodeType::PcData).set_value(txt);
-                        node
-                    });
-                }
-                State::ReadTag
-            }
-            State::End => {
-                break;
-            }
+    /// Parses `contents` into a document owning its strings, so that it can outlive
+    /// `contents`.
+    pub fn load_bytes(&self, contents: &[u8]) -> Result<Document<'static>, LoadError> {
+        let document = match self.input_encoding(contents)? {
+            Encoding::Auto | Encoding::Utf8 => self.parse_owned(contents)?,
+            encoding => self.parse_transcoded(contents, encoding)?,
         };
+        Ok(document)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::fs::File;
-    use std::io::Read;
-    use std::borrow::Borrow;
+    /// Returns a `Reader` of the events of `contents` read with the settings of this parser.
+    /// Its input must be UTF-8: the reader of a document in another encoding only returns
+    /// `ErrorKind::UnsupportedEncoding`.
+    pub fn reader<'a>(&self, contents: &'a [u8]) -> Reader<'a> {
+        let mut reader = self.utf8_reader(contents);
+        reader.error = utf8_only(contents, self.encoding, self.options).err();
+        reader
+    }
 
-    use node::Attribute;
+    fn utf8_reader<'a>(&self, contents: &'a [u8]) -> Reader<'a> {
+        Reader {
+            tokenizer: Tokenizer::new(contents, self),
+            error: None,
+            done: false,
+        }
+    }
 
-    use super::*;
+    /// Returns a `PushParser` reading input fed in chunks with the settings of this parser.
+    /// Its input must be UTF-8: a document in another encoding is reported as
+    /// `ErrorKind::UnsupportedEncoding` as soon as enough of it is fed to tell.
+    pub fn push_parser(&self) -> PushParser {
+        PushParser {
+            encoding: Some(self.encoding),
+            tokenizer: Some(Tokenizer::new(&[], self)),
+            buffer: Vec::new(),
+            cursor: Cursor {
+                position: 0,
+                location: Location {
+                    offset: 0,
+                    line: 1,
+                    column: 1,
+                },
+            },
+            open_tags: Vec::new(),
+            started: false,
+            error: None,
+        }
+    }
 
-    #[test]
-    fn test_parse() {
-        let mut f = File::open("./xml/data1.xml").expect("file not found");
-        let mut contents = String::new();
-        let result = f.read_to_string(&mut contents);
-        assert_eq!(result.is_ok(), true);
+    /// Returns a `DocumentBuilder` building the document the options of this parser describe.
+    pub fn document_builder(&self) -> DocumentBuilder {
+        let mut document = Document {
+            node: Node::new_by_type(NodeType::Document),
+            fragment: self.options.contains(ParseOptions::FRAGMENT),
+        };
+        let builder = Builder::new(&mut document, self.options);
+        DocumentBuilder { document, builder }
+    }
 
-        let _ = parse(contents.as_bytes());
+    fn input_encoding(&self, contents: &[u8]) -> Result<Encoding, ParseXmlError> {
+        input_encoding(contents, self.encoding, self.options)
+    }
 
-        assert_eq!('a' as u8, 97u8);
+    fn parse_utf8<'a>(&self, contents: &'a [u8]) -> Result<Document<'a>, ParseXmlError> {
+        let mut document = Document {
+            node: Node::new_by_type(NodeType::Document),
+            fragment: self.options.contains(ParseOptions::FRAGMENT),
+        };
+        let mut builder = Builder::new(&mut document, self.options);
+        let mut tokenizer = Tokenizer::new(contents, self);
+        parse_internal(&mut tokenizer, |token, attributes, entities| {
+            let mut values = Decoded { contents, entities };
+            builder.token(&mut values, token, attributes)
+        })?;
+        Ok(document)
     }
 
-    #[test]
-    fn bench_parse() {
-        let mut f = File::open("./xml/large.xml").expect("file not found");
-        let mut contents = String::new();
-        let result = f.read_to_string(&mut contents);
-        assert_eq!(result.is_ok(), true);
+    /// Parses `contents`, UTF-8, into a document copying its names and values as they are read.
+    fn parse_owned<'b>(&self, contents: &[u8]) -> Result<Document<'b>, ParseXmlError> {
+        let mut document = Document {
+            node: Node::new_by_type(NodeType::Document),
+            fragment: self.options.contains(ParseOptions::FRAGMENT),
+        };
+        let mut builder = Builder::new(&mut document, self.options);
+        let mut tokenizer = Tokenizer::new(contents, self);
+        parse_internal(&mut tokenizer, |token, attributes, entities| {
+            let mut values = Owned { contents, entities };
+            builder.token(&mut values, token, attributes)
+        })?;
+        Ok(document)
+    }
 
-        let _ = parse(contents.as_bytes());
+    /// Parses `contents`, in `encoding`, transcoded to UTF-8 first.
+    fn parse_transcoded<'b>(
+        &self,
+        contents: &[u8],
+        encoding: Encoding,
+    ) -> Result<Document<'b>, ParseXmlError> {
+        let utf8 = transcode(contents, encoding)?;
+        self.parse_owned(utf8.as_bytes())
+            .map_err(|error| error.into_input(contents, encoding, &utf8))
     }
 
-    #[test]
-    fn test_parse_note_xml() {
-        let mut f = File::open("./xml/note.xml").expect("file not found");
-        let mut contents = String::new();
-        let result = f.read_to_string(&mut contents);
-        assert_eq!(result.is_ok(), true);
+    /// Parses `contents` like `parse`, but decodes references and normalizes whitespace and
+    /// line ends by rewriting each value where it is in `contents`, so that the document
+    /// borrows all of its names and values from it. Only values referencing declared entities,
+    /// whose replacement text may be longer than the reference, are allocated, and only kept
+    /// so when they do not fit where they were.
+    ///
+    /// What `contents` holds afterwards is unspecified, whether parsing succeeds or not.
+    /// Input that is not UTF-8 is transcoded rather than rewritten, like `parse` does.
+    pub fn parse_in_place<'a>(
+        &self,
+        contents: &'a mut [u8],
+    ) -> Result<Document<'a>, ParseXmlError> {
+        match self.input_encoding(contents)? {
+            Encoding::Auto | Encoding::Utf8 => {}
+            _ => return self.parse(contents),
+        }
+        let mut document = Document {
+            node: Node::new_by_type(NodeType::Document),
+            fragment: self.options.contains(ParseOptions::FRAGMENT),
+        };
+        let mut edits = Vec::new();
+        {
+            // the input is only read while parsing, which leaves every name and value as an
+            // edit, checked already
+            let contents: &[u8] = contents;
+            let mut builder = Builder::new(&mut document, self.options);
+            let mut tokenizer = Tokenizer::new(contents, self);
+            parse_internal(&mut tokenizer, |token, attributes, entities| {
+                let mut values = InPlace {
+                    contents,
+                    entities,
+                    edits: &mut edits,
+                };
+                builder.token(&mut values, token, attributes)
+            })?;
+        }
+
+        // then each edit is decoded where it is; the name of a document type declaration is
+        // the start of its value, which comes first
+        edits.sort_by(|a, b| {
+            a.span
+                .start
+                .cmp(&b.span.start)
+                .then(b.span.end.cmp(&a.span.end))
+        });
+        let mut decoded = Vec::with_capacity(edits.len());
+        let mut position = 0;
+        for edit in edits {
+            let Edit {
+                target,
+                span,
+                value,
+            } = edit;
+            if span.start < position {
+                // decoding leaves a value as it is up to its first whitespace
+                decoded.push((target, span.start, span.end));
+                continue;
+            }
+            position = span.end;
 
-        let result = parse(contents.as_bytes());
+            let range = &mut contents[span.start..span.end];
+            let length = match value {
+                Some(value) if value.len() > range.len() => {
+                    target.set(Cow::Owned(value));
+                    continue;
+                }
+                Some(value) => {
+                    range[..value.len()].copy_from_slice(value.as_bytes());
+                    value.len()
+                }
+                None => decode_in_place(range, span.decode),
+            };
+            decoded.push((target, span.start, span.start + length));
+        }
+        let contents: &'a [u8] = contents;
+        for (target, start, end) in decoded {
+            target.set(Cow::Borrowed(to_str(contents, start, end)?));
+        }
+        Ok(document)
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+impl fmt::Debug for Parser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Parser")
+            .field("options", &self.options)
+            .field("max_entity_depth", &self.max_entity_depth)
+            .field("max_entity_expansion", &self.max_entity_expansion)
+            .field("duplicate_attributes", &self.duplicate_attributes)
+            .field("encoding", &self.encoding)
+            .finish()
+    }
+}
+
+/// A general entity declared in the document type declaration.
+enum Entity {
+    /// `<!ENTITY name "value">`, with the character references of the value decoded.
+    Internal(String),
+    /// `<!ENTITY name SYSTEM "uri">` or `<!ENTITY name PUBLIC "id" "uri">`.
+    External {
+        public_id: Option<String>,
+        system_id: String,
+    },
+}
+
+/// The general entities declared in the document type declaration, and how much expanding
+/// them has produced so far.
+struct Entities {
+    declared: HashMap<String, Entity>,
+    // values of declared entities with their own references expanded, filled on first use,
+    // with how deep their references go: first in text, then in attribute values, which
+    // leave external entities out
+    expanded_values: [HashMap<String, (String, usize)>; 2],
+    // the entities being expanded, outermost first
+    expanding: Vec<String>,
+    max_depth: usize,
+    max_expansion: usize,
+    expanded: usize,
+    // whether line ends are normalized in entity values, as ParseOptions::EOL asks
+    eol: bool,
+    resolver: Arc<dyn EntityResolver + Send + Sync>,
+}
+
+impl Entities {
+    fn new(parser: &Parser) -> Entities {
+        Entities {
+            declared: HashMap::new(),
+            expanded_values: [HashMap::new(), HashMap::new()],
+            expanding: Vec::new(),
+            max_depth: parser.max_entity_depth,
+            max_expansion: parser.max_entity_expansion,
+            expanded: 0,
+            eol: parser.options.contains(ParseOptions::EOL),
+            resolver: Arc::clone(&parser.resolver),
+        }
+    }
+
+    /// Returns the content of an external entity or subset, without its byte order mark and
+    /// text declaration, or `None` if the resolver does not provide it.
+    fn resolve(&self, public_id: Option<&str>, system_id: &str) -> Option<Vec<u8>> {
+        let mut contents = self.resolver.resolve(public_id, system_id)?;
+        if contents.starts_with(UTF8_BOM) {
+            contents.drain(..UTF8_BOM.len());
+        }
+        if contents.len() > 5
+            && contents.starts_with(b"<?xml")
+            && CHARTYPE_TABLE[contents[5] as usize] & Chartype::Space as u8 > 0
+        {
+            if let Some(end) = contents.windows(2).position(|w| w == b"?>") {
+                contents.drain(..end + 2);
+            }
+        }
+        Some(contents)
+    }
+}
+
+/// Returns the encoding of `contents`, `encoding` unless it is `Auto`.
+fn input_encoding(
+    contents: &[u8],
+    encoding: Encoding,
+    options: ParseOptions,
+) -> Result<Encoding, ParseXmlError> {
+    if encoding != Encoding::Auto {
+        return Ok(encoding);
+    }
+    match encoding::detect(contents) {
+        Ok(encoding) => Ok(encoding),
+        Err(offset) if options.contains(ParseOptions::STRICT) => Err(ParseXmlError::new(
+            ErrorKind::UnsupportedEncoding,
+            contents,
+            offset,
+        )),
+        Err(_) => Ok(Encoding::Utf8),
+    }
+}
+
+/// Returns the error of reading `contents`, UTF-8 only, if it is in another encoding.
+fn utf8_only(
+    contents: &[u8],
+    encoding: Encoding,
+    options: ParseOptions,
+) -> Result<(), ParseXmlError> {
+    match input_encoding(contents, encoding, options)? {
+        Encoding::Auto | Encoding::Utf8 => Ok(()),
+        _ => Err(ParseXmlError::new(
+            ErrorKind::UnsupportedEncoding,
+            contents,
+            0,
+        )),
+    }
+}
+
+/// Transcodes `contents` from `encoding` to UTF-8.
+fn transcode(contents: &[u8], encoding: Encoding) -> Result<String, ParseXmlError> {
+    encoding::transcode(contents, encoding)
+        .map_err(|offset| ParseXmlError::new(ErrorKind::BadEncoding, contents, offset))
+}
+
+/// Returns `contents[start..end]` as a string slice, without copying it.
+fn to_str(contents: &[u8], start: usize, end: usize) -> Result<&str, ParseXmlError> {
+    str::from_utf8(&contents[start..end]).map_err(|error| {
+        ParseXmlError::new(
+            ErrorKind::InvalidUtf8,
+            contents,
+            start + error.valid_up_to(),
+        )
+    })
+}
+
+/// Ranges of the root element name and of the external ID of a document type declaration,
+/// and where they end.
+struct DoctypeHeader {
+    name: (usize, usize),
+    public_id: Option<(usize, usize)>,
+    system_id: Option<(usize, usize)>,
+    end: usize,
+}
+
+/// Reads the root element name and the optional `SYSTEM "uri"` or `PUBLIC "id" "uri"` external
+/// ID of a document type declaration, starting at `start`.
+fn read_doctype_header(contents: &[u8], start: usize) -> Result<DoctypeHeader, ParseXmlError> {
+    let mut i = start;
+    if CHARTYPE_TABLE[peek!(contents, i) as usize] & Chartype::StartSymBol as u8 == 0 {
+        return Err(ParseXmlError::new(ErrorKind::BadDoctype, contents, i));
+    }
+    skip_chartype!(contents, i, Chartype::Symbol);
+    let name = (start, i);
+
+    let mut public_id = None;
+    let mut system_id = None;
+    let mut end = i;
+    skip_chartype!(contents, i, Chartype::Space);
+    // the input may end in the middle of a keyword, which is not an error in the header
+    let rest = &contents[i..];
+    if b"SYSTEM".starts_with(rest) || b"PUBLIC".starts_with(rest) {
+        return Err(ParseXmlError::eof(contents, contents.len()));
+    }
+    if contents[i..].starts_with(b"SYSTEM") {
+        i += 6;
+        system_id = Some(read_quoted_literal(contents, &mut i)?);
+        end = i;
+    } else if contents[i..].starts_with(b"PUBLIC") {
+        i += 6;
+        public_id = Some(read_quoted_literal(contents, &mut i)?);
+        system_id = Some(read_quoted_literal(contents, &mut i)?);
+        end = i;
+    }
+
+    Ok(DoctypeHeader {
+        name,
+        public_id,
+        system_id,
+        end,
+    })
+}
+
+/// Reads a literal in quotes, preceded by whitespace, and returns the range of its content.
+fn read_quoted_literal(contents: &[u8], i: &mut usize) -> Result<(usize, usize), ParseXmlError> {
+    let mut p = *i;
+    skip_chartype!(contents, p, Chartype::Space);
+    let quote = peek!(contents, p);
+    if p == *i || (quote != QUOTE && quote != APOSTROPHE) {
+        return Err(ParseXmlError::new(ErrorKind::BadDoctype, contents, p));
+    }
+    p += 1;
+    let start = p;
+    scan_char!(contents, p, quote);
+    *i = p + 1;
+    Ok((start, p))
+}
+
+/// Reads the internal subset of a document type declaration, starting right after its `[`,
+/// and returns the position of the `]` closing it.
+///
+/// General entity declarations are added to `entities`; other declarations are skipped.
+/// Brackets are counted so that conditional sections are skipped as a whole, and quoted
+/// literals, comments and processing instructions are skipped without looking at their content.
+///
+/// With `external` set, `contents` is an external subset, which ends with the input rather
+/// than with a `]`.
+fn read_internal_subset(
+    contents: &[u8],
+    start: usize,
+    entities: &mut Entities,
+    external: bool,
+) -> Result<usize, ParseXmlError> {
+    let mut i = start;
+    let mut depth = 0;
+    loop {
+        if external && i >= contents.len() {
+            return Ok(i);
+        }
+        match peek!(contents, i) {
+            quote @ QUOTE | quote @ APOSTROPHE => {
+                i += 1;
+                scan_char!(contents, i, quote);
+            }
+            LESS_THAN if contents[i..].starts_with(b"<!ENTITY") => {
+                i = read_entity_declaration(contents, i + 8, entities)?;
+            }
+            LESS_THAN if contents[i..].starts_with(b"<!--") => {
+                i += 4;
+                while !contents[i..].starts_with(b"-->") {
+                    peek!(contents, i);
+                    i += 1;
+                }
+                i += 2;
+            }
+            LESS_THAN if contents[i..].starts_with(b"<?") => {
+                i += 2;
+                while !contents[i..].starts_with(b"?>") {
+                    peek!(contents, i);
+                    i += 1;
+                }
+                i += 1;
+            }
+            OPEN_BRACKET => depth += 1,
+            CLOSE_BRACKET if depth == 0 => return Ok(i),
+            CLOSE_BRACKET => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+/// Reads an entity declaration starting right after `<!ENTITY` and returns the position of
+/// its closing `>`.
+///
+/// General entities are added to `entities`: internal ones, like `<!ENTITY company "ACME Corp">`,
+/// with their character references already decoded, and external ones, like
+/// `<!ENTITY logo SYSTEM "logo.xml">`, with their external ID. Parameter entities and unparsed
+/// entities, declared with `NDATA`, are skipped. As the specification asks, the first
+/// declaration of an entity is the one that counts.
+fn read_entity_declaration(
+    contents: &[u8],
+    start: usize,
+    entities: &mut Entities,
+) -> Result<usize, ParseXmlError> {
+    let mut i = start;
+    skip_chartype!(contents, i, Chartype::Space);
+    let parameter = peek!(contents, i) == PERCENT;
+    if i == start {
+        return Err(ParseXmlError::new(ErrorKind::BadDoctype, contents, i));
+    }
+    if parameter {
+        i += 1;
+        skip_chartype!(contents, i, Chartype::Space);
+    }
+
+    let name_start = i;
+    if CHARTYPE_TABLE[peek!(contents, i) as usize] & Chartype::StartSymBol as u8 == 0 {
+        return Err(ParseXmlError::new(ErrorKind::BadDoctype, contents, i));
+    }
+    skip_chartype!(contents, i, Chartype::Symbol);
+    let name_end = i;
+
+    let mut p = i;
+    skip_chartype!(contents, p, Chartype::Space);
+    let mut value = None;
+    let mut public_id = None;
+    let mut system_id = None;
+    match peek!(contents, p) {
+        QUOTE | APOSTROPHE => value = Some(read_quoted_literal(contents, &mut i)?),
+        _ if contents[p..].starts_with(b"SYSTEM") => {
+            i = p + 6;
+            system_id = Some(read_quoted_literal(contents, &mut i)?);
+        }
+        _ if contents[p..].starts_with(b"PUBLIC") => {
+            i = p + 6;
+            public_id = Some(read_quoted_literal(contents, &mut i)?);
+            system_id = Some(read_quoted_literal(contents, &mut i)?);
+        }
+        _ => {}
+    }
+    let mut p = i;
+    skip_chartype!(contents, p, Chartype::Space);
+    let unparsed = contents[p..].starts_with(b"NDATA");
+
+    // skip the rest, e.g. an external ID, up to the closing '>'
+    loop {
+        match peek!(contents, i) {
+            quote @ QUOTE | quote @ APOSTROPHE => {
+                i += 1;
+                scan_char!(contents, i, quote);
+            }
+            GREATER_THAN => break,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if parameter || unparsed {
+        return Ok(i);
+    }
+    let name = to_str(contents, name_start, name_end)?.to_owned();
+    if let Entry::Vacant(entry) = entities.declared.entry(name) {
+        if let Some((value_start, value_end)) = value {
+            let mut value = to_str(contents, value_start, value_end)?.to_owned();
+            if entities.eol && value.contains('\r') {
+                value = value.replace("\r\n", "\n").replace('\r', "\n");
+            }
+            let value = decode_character_references(&value).map_err(|(kind, offset)| {
+                ParseXmlError::new(kind, contents, value_start + offset)
+            })?;
+            entry.insert(Entity::Internal(value));
+        } else if let Some((system_start, system_end)) = system_id {
+            let public_id = match public_id {
+                Some((public_start, public_end)) => {
+                    Some(to_str(contents, public_start, public_end)?.to_owned())
+                }
+                None => None,
+            };
+            entry.insert(Entity::External {
+                public_id,
+                system_id: to_str(contents, system_start, system_end)?.to_owned(),
+            });
+        }
+    }
+
+    Ok(i)
+}
+
+/// Decodes the character references of an entity value; entity references are kept, they are
+/// expanded where the entity is used.
+fn decode_character_references(value: &str) -> Result<String, (ErrorKind, usize)> {
+    let mut result = String::with_capacity(value.len());
+    let mut copied = 0;
+    while let Some(offset) = value[copied..].find("&#") {
+        let start = copied + offset;
+        let end = match value[start..].find(';') {
+            Some(length) => start + length,
+            None => return Err((ErrorKind::BadReference, start)),
+        };
+        let c = decode_reference(&value[start + 1..end]).map_err(|kind| (kind, start))?;
+        result.push_str(&value[copied..start]);
+        result.push(c);
+        copied = end + 1;
+    }
+    result.push_str(&value[copied..]);
+    Ok(result)
+}
+
+/// Reads the pseudo-attributes of an XML declaration, found in `contents[start..end]`.
+fn read_declaration(
+    contents: &[u8],
+    start: usize,
+    end: usize,
+) -> Result<Vec<(&str, &str)>, ParseXmlError> {
+    let data = &contents[..end];
+    let mut attributes = Vec::new();
+    let mut i = start;
+    loop {
+        skip_chartype!(data, i, Chartype::Space);
+        if i >= end {
+            break;
+        }
+        if i > start && CHARTYPE_TABLE[data[i - 1] as usize] & Chartype::Space as u8 == 0 {
+            return Err(ParseXmlError::new(ErrorKind::BadDeclaration, contents, i));
+        }
+
+        let name_start = i;
+        skip_chartype!(data, i, Chartype::Symbol);
+        let name_end = i;
+        skip_chartype!(data, i, Chartype::Space);
+        if name_start == name_end || data.get(i) != Some(&EQUAL) {
+            return Err(ParseXmlError::new(ErrorKind::BadDeclaration, contents, i));
+        }
+        i += 1;
+        skip_chartype!(data, i, Chartype::Space);
+
+        let quote = match data.get(i) {
+            Some(&QUOTE) => QUOTE,
+            Some(&APOSTROPHE) => APOSTROPHE,
+            _ => return Err(ParseXmlError::new(ErrorKind::BadDeclaration, contents, i)),
+        };
+        i += 1;
+        let value_start = i;
+        match data[i..].iter().position(|&c| c == quote) {
+            Some(offset) => i += offset,
+            None => return Err(ParseXmlError::new(ErrorKind::BadDeclaration, contents, end)),
+        }
+
+        let name = to_str(contents, name_start, name_end)?;
+        let value = to_str(contents, value_start, i)?;
+        attributes.push((name, value));
+        i += 1;
+    }
+
+    Ok(attributes)
+}
+
+/// Narrows `start..end` so that it neither starts nor ends with whitespace.
+fn trim(contents: &[u8], mut start: usize, mut end: usize) -> (usize, usize) {
+    while start < end && CHARTYPE_TABLE[contents[start] as usize] & Chartype::Space as u8 > 0 {
+        start += 1;
+    }
+    while end > start && CHARTYPE_TABLE[contents[end - 1] as usize] & Chartype::Space as u8 > 0 {
+        end -= 1;
+    }
+    (start, end)
+}
+
+/// Replaces each run of whitespace of `txt` by a single space.
+fn collapse(txt: Cow<str>) -> Cow<str> {
+    let bytes = txt.as_bytes();
+    let unchanged = bytes.iter().enumerate().all(|(i, &c)| match c {
+        b' ' => bytes.get(i + 1) != Some(&b' '),
+        b'\t' | b'\n' | b'\r' => false,
+        _ => true,
+    });
+    if unchanged {
+        return txt;
+    }
+
+    let mut result = String::with_capacity(txt.len());
+    let mut in_whitespace = false;
+    for c in txt.chars() {
+        if c == ' ' || c == '\t' || c == '\n' || c == '\r' {
+            if !in_whitespace {
+                result.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            result.push(c);
+            in_whitespace = false;
+        }
+    }
+    Cow::Owned(result)
+}
+
+/// How a range of the input becomes the value of a node or attribute.
+#[derive(Clone, Copy)]
+enum Decode {
+    /// a name, or a value taken as it is
+    Raw,
+    Text(ParseOptions),
+    Attribute(ParseOptions),
+    /// text with its whitespace runs collapsed
+    Collapsed(ParseOptions),
+}
+
+/// A value of the document: its range in the input, and how to decode it.
+#[derive(Clone, Copy)]
+struct Span {
+    start: usize,
+    end: usize,
+    decode: Decode,
+}
+
+impl Span {
+    fn new(start: usize, end: usize, decode: Decode) -> Span {
+        Span { start, end, decode }
+    }
+
+    fn decode<'a>(
+        self,
+        contents: &'a [u8],
+        entities: &mut Entities,
+    ) -> Result<Cow<'a, str>, ParseXmlError> {
+        let (start, end) = (self.start, self.end);
+        match self.decode {
+            Decode::Raw => to_str(contents, start, end).map(Cow::Borrowed),
+            Decode::Text(options) => to_text(contents, start, end, options, false, entities),
+            Decode::Attribute(options) => {
+                to_attribute_value(contents, start, end, options, entities)
+            }
+            Decode::Collapsed(options) => {
+                to_text(contents, start, end, options, false, entities).map(collapse)
+            }
+        }
+    }
+}
+
+/// A name or value of the document being built.
+#[derive(Clone, Copy, PartialEq)]
+enum Target<'a> {
+    NodeName(*mut Node<'a>),
+    NodeValue(*mut Node<'a>),
+    AttributeName(*mut Attribute<'a>),
+    AttributeValue(*mut Attribute<'a>),
+}
+
+impl<'a> Target<'a> {
+    fn set(self, value: Cow<'a, str>) {
+        match self {
+            Target::NodeName(node) => {
+                unsafe { &mut *node }.set_name(value);
+            }
+            Target::NodeValue(node) => {
+                unsafe { &mut *node }.set_value(value);
+            }
+            Target::AttributeName(attribute) => {
+                unsafe { &mut *attribute }.set_name(value);
+            }
+            Target::AttributeValue(attribute) => {
+                unsafe { &mut *attribute }.set_value(value);
+            }
+        }
+    }
+}
+
+/// How `Builder` turns the names and values it reads, borrowing `'v`, into those of a
+/// document borrowing `'a`.
+trait Values<'a, 'v> {
+    /// A value as it is read, e.g. a `Span` of the input.
+    type Value;
+    type Error;
+
+    /// Sets `target` to `s`, which needs no decoding.
+    fn set_str(&mut self, target: Target<'a>, s: &'v str);
+
+    /// Sets `target` to `value` once decoded, now or after parsing.
+    fn set_value(&mut self, target: Target<'a>, value: Self::Value) -> Result<(), Self::Error>;
+
+    /// Returns `value` decoded right away, for it to be merged into other text.
+    fn text(&mut self, value: Self::Value) -> Result<Cow<'v, str>, Self::Error>;
+
+    /// Sets the value left for later to `target`, if any, so that text can be merged into it.
+    fn resolve(&mut self, target: Target<'a>) -> Result<(), Self::Error>;
+}
+
+/// Values decoded as they are read, borrowing the input unless decoding changes them.
+struct Decoded<'v, 'e> {
+    contents: &'v [u8],
+    entities: &'e mut Entities,
+}
+
+impl<'v, 'e> Values<'v, 'v> for Decoded<'v, 'e> {
+    type Value = Span;
+    type Error = ParseXmlError;
+
+    fn set_str(&mut self, target: Target<'v>, s: &'v str) {
+        target.set(Cow::Borrowed(s));
+    }
+
+    fn set_value(&mut self, target: Target<'v>, span: Span) -> Result<(), ParseXmlError> {
+        target.set(span.decode(self.contents, self.entities)?);
+        Ok(())
+    }
+
+    fn text(&mut self, span: Span) -> Result<Cow<'v, str>, ParseXmlError> {
+        span.decode(self.contents, self.entities)
+    }
+
+    fn resolve(&mut self, _target: Target<'v>) -> Result<(), ParseXmlError> {
+        Ok(())
+    }
+}
+
+/// Values decoded as they are read into strings of their own, for a document that does not
+/// borrow its input.
+struct Owned<'v, 'e> {
+    contents: &'v [u8],
+    entities: &'e mut Entities,
+}
+
+impl<'a, 'v, 'e> Values<'a, 'v> for Owned<'v, 'e> {
+    type Value = Span;
+    type Error = ParseXmlError;
+
+    fn set_str(&mut self, target: Target<'a>, s: &'v str) {
+        target.set(Cow::Owned(s.to_owned()));
+    }
+
+    fn set_value(&mut self, target: Target<'a>, span: Span) -> Result<(), ParseXmlError> {
+        let value = span.decode(self.contents, self.entities)?;
+        target.set(Cow::Owned(value.into_owned()));
+        Ok(())
+    }
+
+    fn text(&mut self, span: Span) -> Result<Cow<'v, str>, ParseXmlError> {
+        span.decode(self.contents, self.entities)
+    }
+
+    fn resolve(&mut self, _target: Target<'a>) -> Result<(), ParseXmlError> {
+        Ok(())
+    }
+}
+
+/// A name or value to set once parsing in place is done, from `span` of the input, or from
+/// `value`, decoded already as it may not fit there.
+struct Edit<'a> {
+    target: Target<'a>,
+    span: Span,
+    value: Option<String>,
+}
+
+/// Values that parsing in place leaves as edits, to be decoded where they are in the input
+/// once it is no longer read.
+struct InPlace<'v, 'e, 'a> {
+    contents: &'v [u8],
+    entities: &'e mut Entities,
+    edits: &'e mut Vec<Edit<'a>>,
+}
+
+impl<'a, 'v, 'e> Values<'a, 'v> for InPlace<'v, 'e, 'a> {
+    type Value = Span;
+    type Error = ParseXmlError;
+
+    fn set_str(&mut self, target: Target<'a>, s: &'v str) {
+        // names are read from the input
+        let start = s.as_ptr() as usize - self.contents.as_ptr() as usize;
+        let span = Span::new(start, start + s.len(), Decode::Raw);
+        self.edits.push(Edit {
+            target,
+            span,
+            value: None,
+        });
+    }
+
+    fn set_value(&mut self, target: Target<'a>, span: Span) -> Result<(), ParseXmlError> {
+        let value = if check_in_place(self.contents, span, self.entities)? {
+            None
+        } else {
+            Some(span.decode(self.contents, self.entities)?.into_owned())
+        };
+        self.edits.push(Edit {
+            target,
+            span,
+            value,
+        });
+        Ok(())
+    }
+
+    fn text(&mut self, span: Span) -> Result<Cow<'v, str>, ParseXmlError> {
+        span.decode(self.contents, self.entities)
+    }
+
+    fn resolve(&mut self, target: Target<'a>) -> Result<(), ParseXmlError> {
+        if self.edits.last().is_some_and(|edit| edit.target == target) {
+            if let Some(edit) = self.edits.pop() {
+                let value = match edit.value {
+                    Some(value) => value,
+                    None => edit.span.decode(self.contents, self.entities)?.into_owned(),
+                };
+                target.set(Cow::Owned(value));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Values already decoded, like those a `Handler` receives, which the document gets copies of.
+struct Ready;
+
+impl<'a, 'v> Values<'a, 'v> for Ready {
+    type Value = &'v str;
+    type Error = Infallible;
+
+    fn set_str(&mut self, target: Target<'a>, s: &'v str) {
+        target.set(Cow::Owned(s.to_owned()));
+    }
+
+    fn set_value(&mut self, target: Target<'a>, value: &'v str) -> Result<(), Infallible> {
+        target.set(Cow::Owned(value.to_owned()));
+        Ok(())
+    }
+
+    fn text(&mut self, value: &'v str) -> Result<Cow<'v, str>, Infallible> {
+        Ok(Cow::Borrowed(value))
+    }
+
+    fn resolve(&mut self, _target: Target<'a>) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// Converts `contents[start..end]` to text, decoding references and normalizing line ends if
+/// `options` asks for it. The text borrows from `contents` unless this changes it.
+/// External entities are left out of attribute values, which `in_attribute` tells apart.
+fn to_text<'a>(
+    contents: &'a [u8],
+    start: usize,
+    end: usize,
+    options: ParseOptions,
+    in_attribute: bool,
+    entities: &mut Entities,
+) -> Result<Cow<'a, str>, ParseXmlError> {
+    let txt = to_str(contents, start, end)?;
+    let escapes = options.contains(ParseOptions::ESCAPES) && txt.as_bytes().contains(&AMPERSAND);
+    let eol = options.contains(ParseOptions::EOL) && txt.as_bytes().contains(&CARRIAGE_RETURN);
+    if escapes || eol {
+        let strict = options.contains(ParseOptions::STRICT);
+        process_text(txt, escapes, eol, strict, in_attribute, entities, 0)
+            .map(|(text, _)| Cow::Owned(text))
+            .map_err(|(kind, offset)| ParseXmlError::new(kind, contents, start + offset))
+    } else {
+        Ok(Cow::Borrowed(txt))
+    }
+}
+
+/// Converts the attribute value `contents[start..end]` to text like `to_text`, also
+/// normalizing its whitespace if `options` asks for it.
+fn to_attribute_value<'a>(
+    contents: &'a [u8],
+    start: usize,
+    end: usize,
+    options: ParseOptions,
+    entities: &mut Entities,
+) -> Result<Cow<'a, str>, ParseXmlError> {
+    if !normalizes_whitespace(&contents[start..end], options) {
+        return to_text(contents, start, end, options, true, entities);
+    }
+    let wnorm = options.contains(ParseOptions::WNORM_ATTRIBUTE);
+
+    // whitespace is normalized before references are decoded, so that the whitespace they
+    // stand for is kept
+    let txt = to_str(contents, start, end)?;
+    let mut normalized = String::with_capacity(txt.len());
+    let mut chars = txt.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                if c == '\r' && chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                if wnorm {
+                    while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = chars.peek() {
+                        chars.next();
+                    }
+                    if normalized.is_empty() || chars.peek().is_none() {
+                        continue;
+                    }
+                }
+                normalized.push(' ');
+            }
+            _ => normalized.push(c),
+        }
+    }
+    if !options.contains(ParseOptions::ESCAPES) || !normalized.contains('&') {
+        return Ok(Cow::Owned(normalized));
+    }
+
+    let strict = options.contains(ParseOptions::STRICT);
+    let decoded = process_text(&normalized, true, false, strict, true, entities, 0);
+    decoded
+        .map(|(text, _)| Cow::Owned(text))
+        .map_err(|(kind, offset)| {
+            // normalizing keeps every '&', the nth of `normalized` is the nth of the input
+            let nth = normalized[..offset].matches('&').count();
+            let offset = txt
+                .match_indices('&')
+                .nth(nth)
+                .map_or(0, |(offset, _)| offset);
+            ParseXmlError::new(kind, contents, start + offset)
+        })
+}
+
+/// Whether `options` changes the whitespace of the attribute value `raw`.
+fn normalizes_whitespace(raw: &[u8], options: ParseOptions) -> bool {
+    let wnorm = options.contains(ParseOptions::WNORM_ATTRIBUTE);
+    let wconv = wnorm || options.contains(ParseOptions::WCONV_ATTRIBUTE);
+    let normalized_already = !raw
+        .iter()
+        .any(|&c| c == b'\t' || c == NEW_LINE || c == CARRIAGE_RETURN)
+        && !(wnorm
+            && (raw.first() == Some(&b' ')
+                || raw.last() == Some(&b' ')
+                || raw.windows(2).any(|pair| pair == b"  ")));
+    wconv && !normalized_already
+}
+
+/// Checks the value `span` of `contents` like decoding it would, returning whether it can be
+/// decoded in place: it cannot if it references a declared entity, whose replacement text may
+/// be longer than the reference.
+fn check_in_place(contents: &[u8], span: Span, entities: &Entities) -> Result<bool, ParseXmlError> {
+    let bytes = to_str(contents, span.start, span.end)?.as_bytes();
+    let options = match span.decode {
+        Decode::Raw => return Ok(true),
+        Decode::Text(options) | Decode::Attribute(options) | Decode::Collapsed(options) => options,
+    };
+    if !options.contains(ParseOptions::ESCAPES) {
+        return Ok(true);
+    }
+
+    let strict = options.contains(ParseOptions::STRICT);
+    let mut i = 0;
+    while let Some(offset) = bytes[i..].iter().position(|&c| c == AMPERSAND) {
+        let start = i + offset;
+        i = start + 1;
+        let error = match reference_at(bytes, start) {
+            Some((_, Ok(_))) => continue,
+            Some((reference, Err(ErrorKind::UndefinedEntity)))
+                if entities.declared.contains_key(reference) =>
+            {
+                return Ok(false);
+            }
+            Some((_, Err(kind))) => kind,
+            None => ErrorKind::BadReference,
+        };
+        if strict {
+            return Err(ParseXmlError::new(error, contents, span.start + start));
+        }
+    }
+    Ok(true)
+}
+
+/// Reads the reference starting with the `&` at `start` of `bytes`, returning its name and
+/// what it decodes to, or `None` if it does not end with `;`.
+fn reference_at(bytes: &[u8], start: usize) -> Option<(&str, Result<char, ErrorKind>)> {
+    let length = bytes[start + 1..]
+        .iter()
+        .take_while(|&&c| c == HASH || CHARTYPE_TABLE[c as usize] & Chartype::Symbol as u8 > 0)
+        .count();
+    let end = start + 1 + length;
+    if bytes.get(end) != Some(&SEMICOLON) {
+        return None;
+    }
+    // names only hold ASCII and the bytes of multibyte characters, `bytes` being UTF-8
+    let reference = str::from_utf8(&bytes[start + 1..end]).ok()?;
+    Some((reference, decode_reference(reference)))
+}
+
+/// Decodes `bytes`, checked by `check_in_place`, where they are, returning the length of the
+/// value. Decoding never makes a value longer when it expands no declared entity, so what is
+/// written never gets ahead of what is read.
+fn decode_in_place(bytes: &mut [u8], decode: Decode) -> usize {
+    let is_space = |c: u8| c == b' ' || c == b'\t' || c == NEW_LINE || c == CARRIAGE_RETURN;
+    let (options, normalize, collapse) = match decode {
+        Decode::Raw => return bytes.len(),
+        Decode::Text(options) => (options, false, false),
+        Decode::Attribute(options) => (options, normalizes_whitespace(bytes, options), false),
+        Decode::Collapsed(options) => (options, false, true),
+    };
+    let escapes = options.contains(ParseOptions::ESCAPES);
+    let eol = options.contains(ParseOptions::EOL) && !normalize;
+    let wnorm = options.contains(ParseOptions::WNORM_ATTRIBUTE);
+    let changes = |c: u8| {
+        (escapes && c == AMPERSAND)
+            || (eol && c == CARRIAGE_RETURN)
+            || ((normalize || collapse) && is_space(c))
+    };
+
+    // the value is left as it is up to its first byte that may change
+    let size = bytes.len();
+    let mut r = match bytes.iter().position(|&c| changes(c)) {
+        Some(offset) => offset,
+        None => return size,
+    };
+    let mut w = r;
+    // whether the last byte written is whitespace, which collapsing does not repeat
+    let mut in_space = false;
+    while r < size {
+        let c = bytes[r];
+        let mut decoded = [0; 4];
+        let written: &[u8] = if escapes && c == AMPERSAND {
+            match reference_at(bytes, r) {
+                Some((reference, Ok(c))) => {
+                    r += reference.len() + 2;
+                    c.encode_utf8(&mut decoded).as_bytes()
+                }
+                // left as it is, like outside of strict mode
+                _ => {
+                    r += 1;
+                    &[AMPERSAND]
+                }
+            }
+        } else if normalize && is_space(c) {
+            // a run of whitespace is one space, or none at either end of the value when
+            // normalizing
+            let run_start = r;
+            r += 1;
+            if wnorm {
+                while r < size && is_space(bytes[r]) {
+                    r += 1;
+                }
+                if run_start == 0 || r == size {
+                    continue;
+                }
+            } else if c == CARRIAGE_RETURN && bytes.get(r) == Some(&NEW_LINE) {
+                r += 1;
+            }
+            b" "
+        } else if eol && c == CARRIAGE_RETURN {
+            r += 1;
+            if bytes.get(r) == Some(&NEW_LINE) {
+                r += 1;
+            }
+            b"\n"
+        } else {
+            r += 1;
+            decoded[0] = c;
+            &decoded[..1]
+        };
+
+        for &c in written {
+            if collapse && is_space(c) {
+                if in_space {
+                    continue;
+                }
+                in_space = true;
+                bytes[w] = b' ';
+            } else {
+                in_space = false;
+                bytes[w] = c;
+            }
+            w += 1;
+        }
+    }
+    w
+}
+
+/// Decodes the references of `txt` if `escapes` is set, and turns `\r\n` and lone `\r` into
+/// `\n` if `eol` is set.
+///
+/// References to declared entities are replaced by their value, itself decoded, `depth` being
+/// how deep in such values `txt` is. In lenient mode a reference that cannot be decoded is kept
+/// as it is, in strict mode it is reported with its offset in `txt`. Going over the limits of
+/// `entities` is always reported.
+fn process_text(
+    txt: &str,
+    escapes: bool,
+    eol: bool,
+    strict: bool,
+    in_attribute: bool,
+    entities: &mut Entities,
+    depth: usize,
+) -> Result<(String, usize), (ErrorKind, usize)> {
+    let bytes = txt.as_bytes();
+    let mut result = String::with_capacity(txt.len());
+    // how many levels of entities deeper than `depth` the references of `txt` go
+    let mut levels = 0;
+    let mut copied = 0;
+    let mut i = 0;
+
+    while let Some(offset) = bytes[i..]
+        .iter()
+        .position(|&c| (escapes && c == AMPERSAND) || (eol && c == CARRIAGE_RETURN))
+    {
+        let start = i + offset;
+        i = start + 1;
+        if bytes[start] == CARRIAGE_RETURN {
+            result.push_str(&txt[copied..start]);
+            result.push('\n');
+            if bytes.get(i) == Some(&NEW_LINE) {
+                i += 1;
+            }
+            copied = i;
+            continue;
+        }
+
+        let (reference, decoded) = match reference_at(bytes, start) {
+            Some(reference) => reference,
+            None if strict => return Err((ErrorKind::BadReference, start)),
+            None => continue,
+        };
+        let end = start + reference.len() + 2;
+        match decoded {
+            Ok(c) => {
+                result.push_str(&txt[copied..start]);
+                result.push(c);
+            }
+            Err(ErrorKind::UndefinedEntity) if entities.declared.contains_key(reference) => {
+                if entities.expanding.iter().any(|name| name == reference) {
+                    return Err((ErrorKind::RecursiveEntity, start));
+                }
+                if depth >= entities.max_depth {
+                    return Err((ErrorKind::EntityExpansionLimit, start));
+                }
+                if !entities.expanded_values[in_attribute as usize].contains_key(reference) {
+                    // the line ends of internal values are normalized where they are declared
+                    let (value, eol) = match &entities.declared[reference] {
+                        Entity::Internal(value) => (value.clone(), false),
+                        Entity::External { .. } if in_attribute && strict => {
+                            return Err((ErrorKind::ExternalEntityInAttribute, start));
+                        }
+                        Entity::External { .. } if in_attribute => continue,
+                        Entity::External {
+                            public_id,
+                            system_id,
+                        } => match entities.resolve(public_id.as_deref(), system_id) {
+                            Some(value) => match String::from_utf8(value) {
+                                Ok(value) => (value, entities.eol),
+                                Err(_) => return Err((ErrorKind::InvalidUtf8, start)),
+                            },
+                            None if strict => return Err((ErrorKind::UnresolvedEntity, start)),
+                            None => continue,
+                        },
+                    };
+                    entities.expanding.push(reference.to_owned());
+                    let expanded =
+                        process_text(&value, true, eol, strict, in_attribute, entities, depth + 1);
+                    entities.expanding.pop();
+                    let expanded = expanded.map_err(|(kind, _)| (kind, start))?;
+                    entities.expanded_values[in_attribute as usize]
+                        .insert(reference.to_owned(), expanded);
+                }
+                // a value expanded before counts as deep as it was then
+                let (expanded, expanded_levels) =
+                    &entities.expanded_values[in_attribute as usize][reference];
+                if depth + 1 + expanded_levels > entities.max_depth {
+                    return Err((ErrorKind::EntityExpansionLimit, start));
+                }
+                levels = levels.max(1 + expanded_levels);
+                entities.expanded += expanded.len();
+                if entities.expanded > entities.max_expansion {
+                    return Err((ErrorKind::EntityExpansionLimit, start));
+                }
+                result.push_str(&txt[copied..start]);
+                result.push_str(expanded);
+            }
+            Err(kind) => {
+                if strict {
+                    return Err((kind, start));
+                }
+                continue;
+            }
+        }
+        copied = end;
+        i = end;
+    }
+    result.push_str(&txt[copied..]);
+
+    Ok((result, levels))
+}
+
+/// Decodes the part of a reference between `&` and `;`.
+fn decode_reference(reference: &str) -> Result<char, ErrorKind> {
+    let code = if let Some(hex) = reference.strip_prefix("#x") {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(decimal) = reference.strip_prefix('#') {
+        decimal.parse::<u32>()
+    } else {
+        return match reference {
+            "lt" => Ok('<'),
+            "gt" => Ok('>'),
+            "amp" => Ok('&'),
+            "apos" => Ok('\''),
+            "quot" => Ok('"'),
+            _ => {
+                let name = reference.as_bytes();
+                let valid_name = !name.is_empty()
+                    && CHARTYPE_TABLE[name[0] as usize] & Chartype::StartSymBol as u8 > 0
+                    && name
+                        .iter()
+                        .all(|&c| CHARTYPE_TABLE[c as usize] & Chartype::Symbol as u8 > 0);
+                if valid_name {
+                    Err(ErrorKind::UndefinedEntity)
+                } else {
+                    Err(ErrorKind::BadReference)
+                }
+            }
+        };
+    };
+
+    // from_str_radix accepts a sign, which references do not
+    match code {
+        Ok(code) if code != 0 && !reference.contains('+') => {
+            char::from_u32(code).ok_or(ErrorKind::BadReference)
+        }
+        _ => Err(ErrorKind::BadReference),
+    }
+}
+
+/// A piece of the document read by `Tokenizer`, its values being left for the consumer to
+/// decode.
+enum Token<'a> {
+    /// A start tag, whose attributes are in `Tokenizer::attributes`.
+    StartElement(&'a str),
+    EndElement(&'a str),
+    Text(Span),
+    CData(Span),
+    Comment(Span),
+    PI(&'a str, Span),
+    Declaration(Vec<(&'a str, &'a str)>),
+    Doctype(&'a str, Span),
+}
+
+/// Reads a document as a sequence of tokens, checking that it is well-formed and dropping
+/// what `ParseOptions` leaves out. Both the DOM parser and `Reader` consume its tokens.
+struct Tokenizer<'a> {
+    contents: &'a [u8],
+    options: ParseOptions,
+    duplicate_attributes: DuplicateAttributes,
+    entities: Entities,
+    // name ranges of the elements that are open, innermost last
+    open_tags: Vec<(usize, usize)>,
+    // whether each open element is in the scope of xml:space="preserve"
+    preserve_space: Vec<bool>,
+    // name of the element whose start tag is being read, and its attributes
+    element: &'a str,
+    attributes: Vec<(&'a str, Span)>,
+    // indexes of the first attributes by name, filled in once there are many of them
+    attribute_indexes: HashMap<&'a str, usize>,
+    // set after the start tag of an empty element, whose end is the next token
+    empty_element: bool,
+    has_root: bool,
+    // where the document starts, after the byte order mark
+    origin: usize,
+    // whether more input may follow, in which case running out of it is not an error yet
+    partial: bool,
+    // where the scan for the end of the token being read started and how far it got, which
+    // is left unfinished when the input runs out
+    scan: Option<(usize, usize)>,
+    // the scan left unfinished by the previous call, which the input to come resumes
+    resume: Option<(usize, usize)>,
+    // where the scan of a start tag or a document type declaration stopped, see `scan_markup`
+    markup: Markup,
+    state: State,
+    i: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(contents: &'a [u8], parser: &Parser) -> Tokenizer<'a> {
+        Tokenizer {
+            contents,
+            options: parser.options,
+            duplicate_attributes: parser.duplicate_attributes,
+            entities: Entities::new(parser),
+            open_tags: Vec::new(),
+            preserve_space: Vec::new(),
+            element: "",
+            attributes: Vec::new(),
+            attribute_indexes: HashMap::new(),
+            empty_element: false,
+            has_root: false,
+            origin: 0,
+            partial: false,
+            scan: None,
+            resume: None,
+            markup: Markup::Outside,
+            state: State::Start,
+            i: 0,
+        }
+    }
+
+    /// Moves the tokenizer to `contents`, which holds the input from `i` on like the current
+    /// one. It must not be in the middle of an empty element.
+    fn with_contents<'b>(self, contents: &'b [u8]) -> Tokenizer<'b> {
+        Tokenizer {
+            contents,
+            options: self.options,
+            duplicate_attributes: self.duplicate_attributes,
+            entities: self.entities,
+            open_tags: self.open_tags,
+            preserve_space: self.preserve_space,
+            element: "",
+            attributes: Vec::new(),
+            attribute_indexes: HashMap::new(),
+            empty_element: false,
+            has_root: self.has_root,
+            origin: self.origin,
+            partial: self.partial,
+            scan: self.scan,
+            resume: self.resume,
+            markup: self.markup,
+            state: self.state,
+            i: self.i,
+        }
+    }
+
+    /// Returns where the scan from `start` for the end of a token goes on, past what was
+    /// scanned before the input ran out. Until `scanned` is called, the scan counts as running
+    /// out of input again, all of it read but for the `overlap` last bytes the end may begin in.
+    fn scan_from(&mut self, start: usize, overlap: usize) -> usize {
+        if self.partial {
+            let end = self.contents.len().saturating_sub(overlap).max(start);
+            self.scan = Some((start, end));
+        }
+        match self.resume {
+            Some((resume_start, resume_end)) if resume_start == start => resume_end,
+            _ => start,
+        }
+    }
+
+    /// Records that the scan started by `scan_from` found the end of its token.
+    fn scanned(&mut self) {
+        self.scan = None;
+    }
+
+    /// Checks that the input holds the '>' ending the start tag or, with `doctype`, the
+    /// document type declaration from `start`, skipping what is quoted and the internal
+    /// subset. Markup cut short is then read once it is complete, rather than again with
+    /// every bit of input, while the scan itself goes on from where it stopped.
+    fn scan_markup(&mut self, start: usize, doctype: bool) -> Result<(), ParseXmlError> {
+        let contents = self.contents;
+        let size = contents.len();
+        let (mut i, mut markup) = match self.resume {
+            Some((resume_start, resume_end)) if resume_start == start => (resume_end, self.markup),
+            _ => (start, Markup::Outside),
+        };
+        // the scan stops before delimiters that the input to come may complete
+        let cut_short = |i: usize, delimiter: &[u8]| {
+            size - i < delimiter.len() && delimiter.starts_with(&contents[i..])
+        };
+        while i < size {
+            let c = contents[i];
+            markup = match markup {
+                Markup::Outside => match c {
+                    GREATER_THAN => {
+                        self.scanned();
+                        return Ok(());
+                    }
+                    QUOTE | APOSTROPHE => Markup::Quoted(c, false),
+                    OPEN_BRACKET if doctype => Markup::InternalSubset,
+                    _ => Markup::Outside,
+                },
+                Markup::Quoted(quote, false) if c == quote => Markup::Outside,
+                Markup::Quoted(quote, true) if c == quote => Markup::InternalSubset,
+                Markup::Quoted(..) => markup,
+                Markup::InternalSubset => match c {
+                    CLOSE_BRACKET => Markup::Outside,
+                    QUOTE | APOSTROPHE => Markup::Quoted(c, true),
+                    LESS_THAN if contents[i..].starts_with(b"<!--") => {
+                        i += 3;
+                        Markup::Comment
+                    }
+                    LESS_THAN if contents[i..].starts_with(b"<?") => {
+                        i += 1;
+                        Markup::PI
+                    }
+                    LESS_THAN if cut_short(i, b"<!--") || cut_short(i, b"<?") => break,
+                    _ => Markup::InternalSubset,
+                },
+                Markup::Comment if contents[i..].starts_with(b"-->") => {
+                    i += 2;
+                    Markup::InternalSubset
+                }
+                Markup::Comment if cut_short(i, b"-->") => break,
+                Markup::PI if contents[i..].starts_with(b"?>") => {
+                    i += 1;
+                    Markup::InternalSubset
+                }
+                Markup::PI if cut_short(i, b"?>") => break,
+                Markup::Comment | Markup::PI => markup,
+            };
+            i += 1;
+        }
+        self.scan = Some((start, i));
+        self.markup = markup;
+        Err(ParseXmlError::eof(contents, size))
+    }
+
+    /// Reads the next token, or returns `None` at the end of the document. If the input runs
+    /// out, `i` is left after the last token read, with the tokens left out.
+    fn next_token(&mut self) -> Result<Option<Token<'a>>, ParseXmlError> {
+        self.resume = self.scan.take();
+        self.read_token().map_err(|error| {
+            // the end of the input is only located once it is the end of the document
+            if error.line == 0 && !self.partial {
+                ParseXmlError::new(error.kind, self.contents, error.offset)
+            } else {
+                error
+            }
+        })
+    }
+
+    fn read_token(&mut self) -> Result<Option<Token<'a>>, ParseXmlError> {
+        if self.empty_element {
+            self.empty_element = false;
+            return Ok(Some(Token::EndElement(self.element)));
+        }
+
+        let contents = self.contents;
+        let options = self.options;
+        let fragment = options.contains(ParseOptions::FRAGMENT);
+        let size = contents.len();
+        let mut i = self.i;
+        let token = loop {
+            // between tokens, where reading starts again if the input runs out, so that the
+            // tokens left out are not read twice
+            if let State::ReadContent = self.state {
+                self.i = i;
+            }
+            self.state = match self.state {
+                State::Start => {
+                    if contents.starts_with(UTF8_BOM) {
+                        i = UTF8_BOM.len();
+                    }
+                    self.origin = i;
+                    State::ReadContent
+                }
+                State::ReadTag => {
+                    i += 1; // skip first '<'
+                    match peek!(contents, i) {
+                        SLASH => {
+                            i += 1;
+                            State::ReadTagClose
+                        }
+                        EXCLAMATION_MARK if contents[i..].starts_with(b"!--") => {
+                            i += 3;
+                            State::ReadComment
+                        }
+                        EXCLAMATION_MARK if contents[i..].starts_with(b"![CDATA[") => {
+                            i += 8;
+                            State::ReadCData
+                        }
+                        QUESTION_MARK => {
+                            i += 1;
+                            State::ReadPI
+                        }
+                        EXCLAMATION_MARK if contents[i..].starts_with(b"!DOCTYPE") => {
+                            i += 8;
+                            State::ReadDoctype
+                        }
+                        EXCLAMATION_MARK => {
+                            let rest = &contents[i..];
+                            let truncated = [&b"!--"[..], b"![CDATA[", b"!DOCTYPE"]
+                                .iter()
+                                .any(|markup| markup.starts_with(rest));
+                            return Err(if truncated {
+                                ParseXmlError::eof(contents, i - 1)
+                            } else {
+                                ParseXmlError::new(ErrorKind::BadStartTag, contents, i - 1)
+                            });
+                        }
+                        _ => State::ReadTagOpen,
+                    }
+                }
+                State::ReadTagOpen => {
+                    let start = i;
+                    if self.partial {
+                        self.scan_markup(start, false)?;
+                    }
+                    if CHARTYPE_TABLE[contents[i] as usize] & Chartype::StartSymBol as u8 == 0 {
+                        return Err(ParseXmlError::new(ErrorKind::InvalidName, contents, i));
+                    }
+                    skip_chartype!(contents, i, Chartype::Symbol);
+                    match peek!(contents, i) {
+                        SLASH | GREATER_THAN => {}
+                        c if CHARTYPE_TABLE[c as usize] & Chartype::Space as u8 > 0 => {}
+                        _ => return Err(ParseXmlError::new(ErrorKind::InvalidName, contents, i)),
+                    }
+
+                    // a document has a single root element, which is only enforced in strict
+                    // mode
+                    if self.has_root
+                        && self.open_tags.is_empty()
+                        && !fragment
+                        && options.contains(ParseOptions::STRICT)
+                    {
+                        return Err(ParseXmlError::new(
+                            ErrorKind::MultipleRoots,
+                            contents,
+                            start - 1,
+                        ));
+                    }
+                    self.has_root = true;
+                    self.open_tags.push((start, i));
+                    let preserve = self.preserve_space.last().cloned().unwrap_or(false);
+                    self.preserve_space.push(preserve);
+                    self.element = to_str(contents, start, i)?;
+                    self.attributes.clear();
+                    self.attribute_indexes.clear();
+
+                    State::ReadAttribute
+                }
+                State::ReadTagClose => {
+                    let start = i;
+                    skip_chartype!(contents, i, Chartype::Symbol);
+                    let end = i;
+                    skip_chartype!(contents, i, Chartype::Space);
+                    if peek!(contents, i) != GREATER_THAN {
+                        return Err(ParseXmlError::new(ErrorKind::BadEndTag, contents, i));
+                    }
+
+                    // start - 2 is the '<' of the closing tag
+                    self.preserve_space.pop();
+                    match self.open_tags.pop() {
+                        None => {
+                            return Err(ParseXmlError::new(
+                                ErrorKind::UnexpectedCloseTag,
+                                contents,
+                                start - 2,
+                            ));
+                        }
+                        Some((open_start, open_end)) => {
+                            if contents[open_start..open_end] != contents[start..end] {
+                                return Err(ParseXmlError::new(
+                                    ErrorKind::MismatchedCloseTag,
+                                    contents,
+                                    start - 2,
+                                ));
+                            }
+                        }
+                    }
+                    let name = to_str(contents, start, end)?;
+                    i += 1; // skip '>'
+                    self.state = State::ReadContent;
+                    break Some(Token::EndElement(name));
+                }
+                State::ReadAttribute => {
+                    skip_chartype!(contents, i, Chartype::Space);
+                    match peek!(contents, i) {
+                        SLASH => {
+                            i += 1;
+                            if peek!(contents, i) != GREATER_THAN {
+                                return Err(ParseXmlError::new(
+                                    ErrorKind::BadStartTag,
+                                    contents,
+                                    i,
+                                ));
+                            }
+                            self.open_tags.pop();
+                            self.preserve_space.pop();
+                            self.empty_element = true;
+                            i += 1; // skip '>'
+                            self.state = State::ReadContent;
+                            break Some(Token::StartElement(self.element));
+                        }
+                        GREATER_THAN => {
+                            i += 1;
+                            self.state = State::ReadContent;
+                            break Some(Token::StartElement(self.element));
+                        }
+                        _ => {
+                            i = self.read_attribute(i)?;
+                            State::ReadAttribute
+                        }
+                    }
+                }
+                State::ReadContent => {
+                    let start = i;
+                    skip_chartype!(contents, i, Chartype::Space);
+                    let text_start = i;
+                    i = i.max(self.scan_from(start, 0));
+                    match contents[i..].iter().position(|&c| c == LESS_THAN) {
+                        Some(offset) => {
+                            i += offset;
+                            self.scanned();
+                        }
+                        // the text may go on in the input to come
+                        None if self.partial => return Err(ParseXmlError::eof(contents, size)),
+                        // input without any markup is cut short, rather than missing its root
+                        None if start == self.origin && !fragment => {
+                            return Err(ParseXmlError::new(
+                                ErrorKind::UnexpectedEof,
+                                contents,
+                                size,
+                            ));
+                        }
+                        // outside of the elements text runs up to the end
+                        None if self.open_tags.is_empty() => i = size,
+                        None => {
+                            // point at the innermost element that is still open
+                            let open_start = self.open_tags[self.open_tags.len() - 1].0;
+                            return Err(ParseXmlError::new(
+                                ErrorKind::UnclosedElement,
+                                contents,
+                                open_start - 1,
+                            ));
+                        }
+                    }
+
+                    let next = if i >= size {
+                        State::End
+                    } else {
+                        State::ReadTag
+                    };
+                    let preserve = self.preserve_space.last() == Some(&true);
+                    let whitespace_only = i == text_start;
+                    if !whitespace_only
+                        && !fragment
+                        && self.open_tags.is_empty()
+                        && options.contains(ParseOptions::STRICT)
+                    {
+                        return Err(ParseXmlError::new(
+                            ErrorKind::TextOutsideRoot,
+                            contents,
+                            text_start,
+                        ));
+                    }
+                    let keep =
+                        !whitespace_only || preserve || options.contains(ParseOptions::WS_PCDATA);
+                    if i > start && keep && (fragment || !self.open_tags.is_empty()) {
+                        let (start, end) =
+                            if options.contains(ParseOptions::TRIM_PCDATA) && !preserve {
+                                trim(contents, start, i)
+                            } else {
+                                (start, i)
+                            };
+                        let decode = if options.contains(ParseOptions::COLLAPSE_PCDATA) && !preserve
+                        {
+                            Decode::Collapsed(options)
+                        } else {
+                            Decode::Text(options)
+                        };
+                        self.state = next;
+                        break Some(Token::Text(Span::new(start, end, decode)));
+                    }
+                    next
+                }
+                State::ReadComment => {
+                    let start = i;
+                    i = self.scan_from(start, 2);
+                    loop {
+                        scan_char!(contents, i, DASH);
+                        if peek!(contents, i + 1) == DASH {
+                            if peek!(contents, i + 2) == GREATER_THAN {
+                                break;
+                            }
+                            if options.contains(ParseOptions::STRICT) {
+                                return Err(ParseXmlError::new(ErrorKind::BadComment, contents, i));
+                            }
+                        }
+                        i += 1;
+                    }
+
+                    self.scanned();
+                    let end = i;
+                    i += 3; // skip "-->"
+                    self.state = State::ReadContent;
+                    if options.contains(ParseOptions::COMMENTS) {
+                        // comments are kept as written, only line ends are normalized
+                        let decode = Decode::Text(options & !ParseOptions::ESCAPES);
+                        break Some(Token::Comment(Span::new(start, end, decode)));
+                    }
+                    State::ReadContent
+                }
+                State::ReadCData => {
+                    let start = i;
+                    i = self.scan_from(start, 2);
+                    loop {
+                        scan_chartype!(contents, i, Chartype::ParseCData);
+                        if contents[i..].starts_with(b"]]>") {
+                            break;
+                        }
+                        i += 1;
+                    }
+
+                    self.scanned();
+                    let end = i;
+                    i += 3; // skip "]]>"
+                    self.state = State::ReadContent;
+                    if fragment || !self.open_tags.is_empty() {
+                        // CDATA is never decoded, only line ends are normalized
+                        let decode = Decode::Text(options & !ParseOptions::ESCAPES);
+                        break Some(Token::CData(Span::new(start, end, decode)));
+                    }
+                    State::ReadContent
+                }
+                State::ReadPI => {
+                    let target_start = i;
+                    if CHARTYPE_TABLE[peek!(contents, i) as usize] & Chartype::StartSymBol as u8
+                        == 0
+                    {
+                        return Err(ParseXmlError::new(ErrorKind::BadPI, contents, i));
+                    }
+                    skip_chartype!(contents, i, Chartype::Symbol);
+                    let target_end = i;
+
+                    // the data is separated from the target by whitespace, and may be empty
+                    let data_start = if peek!(contents, i) == QUESTION_MARK
+                        && peek!(contents, i + 1) == GREATER_THAN
+                    {
+                        i
+                    } else {
+                        if CHARTYPE_TABLE[peek!(contents, i) as usize] & Chartype::Space as u8 == 0
+                        {
+                            return Err(ParseXmlError::new(ErrorKind::BadPI, contents, i));
+                        }
+                        skip_chartype!(contents, i, Chartype::Space);
+                        let data_start = i;
+                        i = self.scan_from(data_start, 1);
+                        loop {
+                            scan_char!(contents, i, QUESTION_MARK);
+                            if peek!(contents, i + 1) == GREATER_THAN {
+                                break;
+                            }
+                            i += 1;
+                        }
+                        self.scanned();
+                        data_start
+                    };
+
+                    let data_end = i;
+                    i += 2; // skip "?>"
+                    self.state = State::ReadContent;
+                    // <?xml ...?> is the XML declaration, not a processing instruction
+                    let is_declaration =
+                        contents[target_start..target_end].eq_ignore_ascii_case(b"xml");
+                    if is_declaration {
+                        // the declaration must come first, which is only enforced in strict
+                        // mode; leniently one after the root element is ignored
+                        let first = target_start - 2 == self.origin;
+                        if options.contains(ParseOptions::STRICT) && !first {
+                            return Err(ParseXmlError::new(
+                                ErrorKind::MisplacedDeclaration,
+                                contents,
+                                target_start - 2,
+                            ));
+                        }
+                        // it is checked even when it is left out, like the rest of the markup
+                        let attributes = read_declaration(contents, data_start, data_end)?;
+                        if options.contains(ParseOptions::DECLARATION) && !self.has_root {
+                            break Some(Token::Declaration(attributes));
+                        }
+                    } else if options.contains(ParseOptions::PI) {
+                        let target = to_str(contents, target_start, target_end)?;
+                        let decode = Decode::Text(options & !ParseOptions::ESCAPES);
+                        break Some(Token::PI(target, Span::new(data_start, data_end, decode)));
+                    }
+                    State::ReadContent
+                }
+                State::ReadDoctype => {
+                    let doctype_start = i - 9;
+                    if self.partial {
+                        self.scan_markup(i, true)?;
+                    }
+                    if self.has_root {
+                        return Err(ParseXmlError::new(
+                            ErrorKind::BadDoctype,
+                            contents,
+                            doctype_start,
+                        ));
+                    }
+                    if CHARTYPE_TABLE[peek!(contents, i) as usize] & Chartype::Space as u8 == 0 {
+                        return Err(ParseXmlError::new(ErrorKind::BadDoctype, contents, i));
+                    }
+                    skip_chartype!(contents, i, Chartype::Space);
+                    let value_start = i;
+
+                    let header = read_doctype_header(contents, i)?;
+                    i = header.end;
+                    skip_chartype!(contents, i, Chartype::Space);
+                    if peek!(contents, i) == OPEN_BRACKET {
+                        i = read_internal_subset(contents, i + 1, &mut self.entities, false)? + 1;
+                        skip_chartype!(contents, i, Chartype::Space);
+                    }
+                    if peek!(contents, i) != GREATER_THAN {
+                        return Err(ParseXmlError::new(ErrorKind::BadDoctype, contents, i));
+                    }
+
+                    // the external subset is read after the internal one, whose declarations
+                    // take precedence; its errors are reported at its system ID
+                    if let Some((system_start, system_end)) = header.system_id {
+                        let public_id = match header.public_id {
+                            Some((start, end)) => Some(to_str(contents, start, end)?),
+                            None => None,
+                        };
+                        let system_id = to_str(contents, system_start, system_end)?;
+                        if let Some(subset) = self.entities.resolve(public_id, system_id) {
+                            read_internal_subset(&subset, 0, &mut self.entities, true).map_err(
+                                |error| ParseXmlError::new(error.kind, contents, system_start),
+                            )?;
+                        }
+                    }
+
+                    let (_, value_end) = trim(contents, value_start, i);
+                    i += 1; // skip '>'
+                    self.state = State::ReadContent;
+                    if options.contains(ParseOptions::DOCTYPE) {
+                        let name = to_str(contents, header.name.0, header.name.1)?;
+                        let decode = Decode::Text(options & !ParseOptions::ESCAPES);
+                        break Some(Token::Doctype(
+                            name,
+                            Span::new(value_start, value_end, decode),
+                        ));
+                    }
+                    State::ReadContent
+                }
+                State::End => {
+                    if !self.has_root && !fragment {
+                        return Err(ParseXmlError::new(ErrorKind::NoRootElement, contents, size));
+                    }
+                    break None;
+                }
+            };
+        };
+        self.i = i;
+        Ok(token)
+    }
+
+    /// Reads the next token as an event, decoding its values.
+    fn next_event(&mut self) -> Result<Option<Event<'a>>, ParseXmlError> {
+        let contents = self.contents;
+        let token = match self.next_token()? {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        let entities = &mut self.entities;
+        let event = match token {
+            Token::StartElement(name) => {
+                let attributes = self
+                    .attributes
+                    .iter()
+                    .map(|&(name, span)| Ok((name, span.decode(contents, entities)?)))
+                    .collect::<Result<_, ParseXmlError>>()?;
+                Event::StartElement { name, attributes }
+            }
+            Token::EndElement(name) => Event::EndElement { name },
+            Token::Text(span) => Event::Text(span.decode(contents, entities)?),
+            Token::CData(span) => Event::CData(span.decode(contents, entities)?),
+            Token::Comment(span) => Event::Comment(span.decode(contents, entities)?),
+            Token::PI(target, span) => Event::PI {
+                target,
+                data: span.decode(contents, entities)?,
+            },
+            Token::Declaration(attributes) => Event::Declaration { attributes },
+            Token::Doctype(name, span) => Event::Doctype {
+                name,
+                value: span.decode(contents, entities)?,
+            },
+        };
+        Ok(Some(event))
+    }
+
+    /// Reads the attribute starting at `i` into `attributes`, returning where it ends.
+    fn read_attribute(&mut self, mut i: usize) -> Result<usize, ParseXmlError> {
+        let contents = self.contents;
+        let options = self.options;
+        let start = i;
+        if CHARTYPE_TABLE[contents[i] as usize] & Chartype::StartSymBol as u8 == 0 {
+            return Err(ParseXmlError::new(ErrorKind::InvalidName, contents, i));
+        }
+        skip_chartype!(contents, i, Chartype::Symbol);
+        let end = i;
+        skip_chartype!(contents, i, Chartype::Space);
+        if peek!(contents, i) != EQUAL {
+            return Err(ParseXmlError::new(ErrorKind::BadAttribute, contents, i));
+        }
+        i += 1; //skip =
+        skip_chartype!(contents, i, Chartype::Space);
+        let quote = peek!(contents, i);
+        if quote != QUOTE && quote != APOSTROPHE {
+            return Err(ParseXmlError::new(ErrorKind::BadAttribute, contents, i));
+        }
+        i += 1;
+        let value_start = i;
+        scan_char!(contents, i, quote);
+        if options.contains(ParseOptions::STRICT) {
+            if let Some(offset) = contents[value_start..i]
+                .iter()
+                .position(|&c| c == LESS_THAN)
+            {
+                return Err(ParseXmlError::new(
+                    ErrorKind::BadAttribute,
+                    contents,
+                    value_start + offset,
+                ));
+            }
+        }
+
+        let name = to_str(contents, start, end)?;
+        let value = Span::new(value_start, i, Decode::Attribute(options));
+        let duplicate = self.find_attribute(name);
+        if duplicate.is_some() && options.contains(ParseOptions::STRICT) {
+            return Err(ParseXmlError::new(
+                ErrorKind::DuplicateAttribute,
+                contents,
+                start,
+            ));
+        }
+        let kept = match duplicate {
+            None => {
+                self.attributes.push((name, value));
+                true
+            }
+            Some(index) if self.duplicate_attributes == DuplicateAttributes::KeepLast => {
+                self.attributes[index].1 = value;
+                true
+            }
+            Some(_) => false,
+        };
+        if kept && name == "xml:space" {
+            let value = value.decode(contents, &mut self.entities)?;
+            if let Some(preserve) = self.preserve_space.last_mut() {
+                match &*value {
+                    "preserve" => *preserve = true,
+                    "default" => *preserve = false,
+                    _ => {}
+                }
+            }
+        }
+
+        i += 1;
+        // attributes are separated by whitespace
+        match peek!(contents, i) {
+            SLASH | GREATER_THAN => {}
+            c if CHARTYPE_TABLE[c as usize] & Chartype::Space as u8 > 0 => {}
+            _ => return Err(ParseXmlError::new(ErrorKind::BadAttribute, contents, i)),
+        }
+        Ok(i)
+    }
+
+    /// Returns the index of the attribute named `name` in the start tag being read, looked up
+    /// by name once a linear search would be slow.
+    fn find_attribute(&mut self, name: &str) -> Option<usize> {
+        if self.attributes.len() < INDEXED_ATTRIBUTES {
+            return self.attributes.iter().position(|&(other, _)| other == name);
+        }
+        let indexed = self.attribute_indexes.len();
+        for (index, &(other, _)) in self.attributes.iter().enumerate().skip(indexed) {
+            self.attribute_indexes.insert(other, index);
+        }
+        self.attribute_indexes.get(name).cloned()
+    }
+}
+
+/// Passes the tokens of `tokenizer` to `build`, with the attributes of start tags and the
+/// entities values are decoded with.
+fn parse_internal<'v, F>(tokenizer: &mut Tokenizer<'v>, mut build: F) -> Result<(), ParseXmlError>
+where
+    F: FnMut(Token<'v>, &[(&'v str, Span)], &mut Entities) -> Result<(), ParseXmlError>,
+{
+    while let Some(token) = tokenizer.next_token()? {
+        build(token, &tokenizer.attributes, &mut tokenizer.entities)?;
+    }
+    Ok(())
+}
+
+/// Builds a document from what is read of it, for both the DOM parser and `DocumentBuilder`,
+/// leaving how names and values are stored to `Values`.
+#[derive(Debug)]
+struct Builder<'a> {
+    // the node new nodes are appended to
+    current: *mut Node<'a>,
+    cdata: bool,
+    // set after CDATA is merged into text, so that the text following it is merged as well
+    merge_pcdata: bool,
+}
+
+impl<'a> Builder<'a> {
+    fn new(document: &mut Document<'a>, options: ParseOptions) -> Builder<'a> {
+        Builder {
+            current: document.node.borrow_mut(),
+            cdata: options.contains(ParseOptions::CDATA),
+            merge_pcdata: false,
+        }
+    }
+
+    fn current(&mut self) -> &mut Node<'a> {
+        self.merge_pcdata = false;
+        unsafe { &mut *self.current }
+    }
+
+    /// Adds `token` to the document, `attributes` being those of a start tag.
+    fn token<'v, V>(
+        &mut self,
+        values: &mut V,
+        token: Token<'v>,
+        attributes: &[(&'v str, Span)],
+    ) -> Result<(), V::Error>
+    where
+        V: Values<'a, 'v, Value = Span>,
+    {
+        match token {
+            Token::StartElement(name) => {
+                self.start_element(values, name, attributes.iter().cloned())
+            }
+            Token::EndElement(_) => {
+                self.end_element();
+                Ok(())
+            }
+            Token::Text(span) => self.text(values, span),
+            Token::CData(span) => self.cdata(values, span),
+            Token::Comment(span) => self.comment(values, span),
+            Token::PI(target, span) => self.processing_instruction(values, target, span),
+            Token::Declaration(attributes) => {
+                self.declaration(values, &attributes);
+                Ok(())
+            }
+            Token::Doctype(name, span) => self.doctype(values, name, span),
+        }
+    }
+
+    fn start_element<'v, V, I>(
+        &mut self,
+        values: &mut V,
+        name: &'v str,
+        attributes: I,
+    ) -> Result<(), V::Error>
+    where
+        V: Values<'a, 'v>,
+        I: IntoIterator<Item = (&'v str, V::Value)>,
+    {
+        let element = self.current().append_child("");
+        values.set_str(Target::NodeName(element), name);
+        for (name, value) in attributes {
+            let attribute = element.append_attribute("", "");
+            values.set_str(Target::AttributeName(attribute), name);
+            values.set_value(Target::AttributeValue(attribute), value)?;
+        }
+        self.current = element;
+        Ok(())
+    }
+
+    fn end_element(&mut self) {
+        if let Some(parent) = self.current().parent_mut() {
+            self.current = parent;
+        }
+    }
+
+    fn text<'v, V: Values<'a, 'v>>(
+        &mut self,
+        values: &mut V,
+        value: V::Value,
+    ) -> Result<(), V::Error> {
+        let merge = self.merge_pcdata;
+        let node = self.current();
+        if merge {
+            if let Some(last) = node.last_child_mut() {
+                if *last.node_type() == NodeType::PcData {
+                    values.resolve(Target::NodeValue(last))?;
+                    let merged = last.value().to_owned() + &values.text(value)?;
+                    last.set_value(merged);
+                    return Ok(());
+                }
+            }
+        }
+        let text = node.append_child_by_type(NodeType::PcData);
+        values.set_value(Target::NodeValue(text), value)
+    }
+
+    /// Appends a CDATA section, or merges it into the text around it if CDATA sections are
+    /// not kept as such.
+    fn cdata<'v, V: Values<'a, 'v>>(
+        &mut self,
+        values: &mut V,
+        value: V::Value,
+    ) -> Result<(), V::Error> {
+        let cdata = self.cdata;
+        let node = self.current();
+        if cdata {
+            let section = node.append_child_by_type(NodeType::CData);
+            return values.set_value(Target::NodeValue(section), value);
+        }
+
+        match node.last_child_mut() {
+            Some(last) if *last.node_type() == NodeType::PcData => {
+                values.resolve(Target::NodeValue(last))?;
+                let merged = last.value().to_owned() + &values.text(value)?;
+                last.set_value(merged);
+            }
+            _ => {
+                let text = node.append_child_by_type(NodeType::PcData);
+                values.set_value(Target::NodeValue(text), value)?;
+            }
+        }
+        self.merge_pcdata = true;
+        Ok(())
+    }
+
+    fn comment<'v, V: Values<'a, 'v>>(
+        &mut self,
+        values: &mut V,
+        value: V::Value,
+    ) -> Result<(), V::Error> {
+        let comment = self.current().append_child_by_type(NodeType::Comment);
+        values.set_value(Target::NodeValue(comment), value)
+    }
+
+    fn processing_instruction<'v, V: Values<'a, 'v>>(
+        &mut self,
+        values: &mut V,
+        target: &'v str,
+        data: V::Value,
+    ) -> Result<(), V::Error> {
+        let pi = self
+            .current()
+            .append_child_by_type(NodeType::ProcessingInstruction);
+        values.set_str(Target::NodeName(pi), target);
+        values.set_value(Target::NodeValue(pi), data)
+    }
+
+    fn declaration<'v, V: Values<'a, 'v>>(
+        &mut self,
+        values: &mut V,
+        attributes: &[(&'v str, &'v str)],
+    ) {
+        let declaration = self
+            .current()
+            .append_child_by_type(NodeType::Declaration)
+            .set_name("xml");
+        for &(name, value) in attributes {
+            let attribute = declaration.append_attribute("", "");
+            values.set_str(Target::AttributeName(attribute), name);
+            values.set_str(Target::AttributeValue(attribute), value);
+        }
+    }
+
+    fn doctype<'v, V: Values<'a, 'v>>(
+        &mut self,
+        values: &mut V,
+        name: &'v str,
+        value: V::Value,
+    ) -> Result<(), V::Error> {
+        let doctype = self.current().append_child_by_type(NodeType::Doctype);
+        values.set_str(Target::NodeName(doctype), name);
+        values.set_value(Target::NodeValue(doctype), value)
+    }
+}
+
+/// Passes `event` to the callback of `handler` for it.
+fn handle_event<H: Handler>(handler: &mut H, event: Event) -> Result<(), H::Error> {
+    match event {
+        Event::StartElement { name, attributes } => handler.start_element(name, &attributes),
+        Event::EndElement { name } => handler.end_element(name),
+        Event::Text(text) => handler.characters(&text),
+        Event::CData(text) => handler.cdata(&text),
+        Event::Comment(text) => handler.comment(&text),
+        Event::PI { target, data } => handler.processing_instruction(target, &data),
+        Event::Declaration { attributes } => handler.declaration(&attributes),
+        Event::Doctype { name, value } => handler.doctype(name, &value),
+    }
+}
+
+/// What `Reader` reads from a document, in document order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// A start tag, with its attributes in the order they are written. An empty element,
+    /// `<a/>`, is read as a start tag followed by an end tag.
+    StartElement {
+        name: &'a str,
+        attributes: Vec<(&'a str, Cow<'a, str>)>,
+    },
+    EndElement {
+        name: &'a str,
+    },
+    Text(Cow<'a, str>),
+    /// A CDATA section, reported as such whether or not `ParseOptions::CDATA` is set.
+    CData(Cow<'a, str>),
+    Comment(Cow<'a, str>),
+    PI {
+        target: &'a str,
+        data: Cow<'a, str>,
+    },
+    /// The XML declaration, with its pseudo-attributes.
+    Declaration {
+        attributes: Vec<(&'a str, &'a str)>,
+    },
+    /// The document type declaration, `value` being what follows `<!DOCTYPE`.
+    Doctype {
+        name: &'a str,
+        value: Cow<'a, str>,
+    },
+}
+
+/// Reads a document as a sequence of events rather than as a tree, without keeping more
+/// than the names of the open elements in memory.
+///
+/// The events follow the options of the `Parser` the reader comes from: comments are only
+/// read with `ParseOptions::COMMENTS`, text is trimmed with `ParseOptions::TRIM_PCDATA`, and so
+/// on. The reader stops after the first error, or at the end of the document.
+///
+/// ```
+/// use dummy_xml::parser::{Event, Reader};
+///
+/// let mut reader = Reader::new(b"<a x='1'>hello<b/></a>");
+/// assert_eq!(
+///     reader.next().unwrap().unwrap(),
+///     Event::StartElement {
+///         name: "a",
+///         attributes: vec![("x", "1".into())],
+///     }
+/// );
+/// assert_eq!(reader.next().unwrap().unwrap(), Event::Text("hello".into()));
+/// assert_eq!(reader.count(), 3);
+/// ```
+///
+/// Unlike `Parser::parse`, the reader does not transcode its input, which must be UTF-8.
+/// Input in another encoding is an `ErrorKind::UnsupportedEncoding` error.
+pub struct Reader<'a> {
+    tokenizer: Tokenizer<'a>,
+    // the error of the input not being UTF-8, returned first
+    error: Option<ParseXmlError>,
+    done: bool,
+}
+
+impl<'a> Reader<'a> {
+    /// A reader of `contents` with `ParseOptions::DEFAULT`.
+    pub fn new(contents: &'a [u8]) -> Reader<'a> {
+        Parser::new().reader(contents)
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<Event<'a>, ParseXmlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(error) = self.error.take() {
+            self.done = true;
+            return Some(Err(error));
+        }
+        let event = self.tokenizer.next_event();
+        self.done = !matches!(event, Ok(Some(_)));
+        event.transpose()
+    }
+}
+
+impl<'a> fmt::Debug for Reader<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reader")
+            .field("offset", &self.tokenizer.i)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+/// Where a byte is in the input, as `ParseXmlError` reports it.
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+/// The location of a position in the buffer of a `PushParser`.
+#[derive(Debug, Clone, Copy)]
+struct Cursor {
+    position: usize,
+    location: Location,
+}
+
+impl Cursor {
+    /// Moves the cursor forward to `position` of `contents`.
+    fn advance(&mut self, contents: &[u8], position: usize) {
+        for &c in &contents[self.position..position] {
+            if c == NEW_LINE {
+                self.location.line += 1;
+                self.location.column = 1;
+            } else if c & 0xC0 != 0x80 {
+                self.location.column += 1;
+            }
+        }
+        self.location.offset += position - self.position;
+        self.position = position;
+    }
+}
+
+/// Parses a document fed in chunks, e.g. as it arrives from a socket, into the callbacks of a
+/// `Handler`. Chunks may end anywhere, in the middle of a tag, of a reference or of a
+/// character: what cannot be read yet is kept until the following chunks complete it.
+///
+/// ```
+/// use dummy_xml::parser::Parser;
+///
+/// let parser = Parser::new();
+/// let mut push_parser = parser.push_parser();
+/// let mut builder = parser.document_builder();
+/// for chunk in [&b"<a x='1'><b>te"[..], b"xt</b", b"></a>"].iter() {
+///     push_parser.feed(chunk, &mut builder).unwrap();
+/// }
+/// push_parser.finish(&mut builder).unwrap();
+///
+/// let document = builder.into_document();
+/// assert_eq!(document.root().first_child().unwrap().name(), "b");
+/// ```
+///
+/// Only the input not read yet is kept, along with the names of the open elements. Like
+/// `Reader`, the push parser does not transcode its input, which must be UTF-8: input in
+/// another encoding is an `ErrorKind::UnsupportedEncoding` error.
+pub struct PushParser {
+    // the encoding set on the parser, until the input is known to be UTF-8
+    encoding: Option<Encoding>,
+    // None once an error stopped parsing
+    tokenizer: Option<Tokenizer<'static>>,
+    // the input from `tokenizer.i` on, after the names of the open elements
+    buffer: Vec<u8>,
+    // at `tokenizer.i`, where the input not read yet starts
+    cursor: Cursor,
+    // locations of the start tags of the open elements, which the buffer no longer has
+    open_tags: Vec<Location>,
+    started: bool,
+    // the error that stopped parsing, returned again by the calls that follow
+    error: Option<ParseXmlError>,
+}
+
+impl PushParser {
+    /// Parses `chunk`, the continuation of the chunks fed so far, passing what it completes
+    /// to `handler`.
+    ///
+    /// Once the input is found to be malformed, this and `finish` return the same error again.
+    /// An error of `handler` leaves the event it was passed read, parsing resumes after it.
+    pub fn feed<H: Handler>(
+        &mut self,
+        chunk: &[u8],
+        handler: &mut H,
+    ) -> Result<(), SaxError<H::Error>> {
+        self.buffer.extend_from_slice(chunk);
+        // every token ends with a '>' or before a '<', which is then what completes one
+        if chunk.iter().any(|&c| c == LESS_THAN || c == GREATER_THAN) {
+            self.read(handler, true)?;
+        }
+        Ok(())
+    }
+
+    /// Parses what is left of the input, which ends there, reporting it if it is truncated.
+    pub fn finish<H: Handler>(mut self, handler: &mut H) -> Result<(), SaxError<H::Error>> {
+        self.read(handler, false)?;
+        handler.end_document().map_err(SaxError::Handler)
+    }
+
+    /// Passes the events of the buffer to `handler`, up to the end of the last complete one
+    /// if more input may follow.
+    fn read<H: Handler>(
+        &mut self,
+        handler: &mut H,
+        partial: bool,
+    ) -> Result<(), SaxError<H::Error>> {
+        if let Some(ref error) = self.error {
+            return Err(SaxError::Parse(error.clone()));
+        }
+        if !self.started {
+            self.started = true;
+            handler.start_document().map_err(SaxError::Handler)?;
+        }
+        if let Some(encoding) = self.encoding {
+            if partial && encoding == Encoding::Auto && encoding::is_undetermined(&self.buffer) {
+                return Ok(());
+            }
+            let options = self
+                .tokenizer
+                .as_ref()
+                .expect("no tokenizer without an error")
+                .options;
+            if let Err(error) = utf8_only(&self.buffer, encoding, options) {
+                return Err(self.stop(error));
+            }
+            self.encoding = None;
+        }
+        let contents = &self.buffer[..];
+        let mut tokenizer = self
+            .tokenizer
+            .take()
+            .expect("no tokenizer without an error")
+            .with_contents(contents);
+        tokenizer.partial = partial;
+        let mut result = Ok(());
+        loop {
+            let (state, i, has_root) = (tokenizer.state, tokenizer.i, tokenizer.has_root);
+            let (open_tags, preserve_space) =
+                (tokenizer.open_tags.len(), tokenizer.preserve_space.len());
+            let expanded = tokenizer.entities.expanded;
+            let event = match tokenizer.next_event() {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                // the input to come may complete the token, which is then read again from where
+                // its scan stopped
+                Err(ref error) if partial && error.kind == ErrorKind::UnexpectedEof => {
+                    if tokenizer.i == i {
+                        tokenizer.state = state;
+                    } else {
+                        // the tokens left out before it are not read again
+                        tokenizer.state = State::ReadContent;
+                        self.cursor.advance(contents, tokenizer.i);
+                    }
+                    tokenizer.has_root = has_root;
+                    tokenizer.open_tags.truncate(open_tags);
+                    tokenizer.preserve_space.truncate(preserve_space);
+                    tokenizer.entities.expanded = expanded;
+                    break;
+                }
+                Err(mut error) => {
+                    let location = if error.kind == ErrorKind::UnclosedElement {
+                        self.open_tags[self.open_tags.len() - 1]
+                    } else {
+                        let mut cursor = self.cursor;
+                        cursor.advance(contents, error.offset.max(cursor.position));
+                        cursor.location
+                    };
+                    error.offset = location.offset;
+                    error.line = location.line;
+                    error.column = location.column;
+                    return Err(self.stop(error));
+                }
+            };
+
+            if tokenizer.open_tags.len() > self.open_tags.len() {
+                let (start, _) = tokenizer.open_tags[tokenizer.open_tags.len() - 1];
+                self.cursor.advance(contents, start - 1);
+                self.open_tags.push(self.cursor.location);
+            }
+            self.open_tags.truncate(tokenizer.open_tags.len());
+            self.cursor.advance(contents, tokenizer.i);
+            if let Err(error) = handle_event(handler, event) {
+                result = Err(SaxError::Handler(error));
+                break;
+            }
+        }
+        let mut tokenizer = tokenizer.with_contents(&[]);
+
+        // drop what is read once it is most of the buffer, keeping the names of the open
+        // elements for their end tags to be matched
+        if tokenizer.i > 0 && tokenizer.i >= self.buffer.len() / 2 {
+            let mut buffer = Vec::with_capacity(self.buffer.len() - tokenizer.i);
+            for tag in &mut tokenizer.open_tags {
+                buffer.push(LESS_THAN);
+                let start = buffer.len();
+                buffer.extend_from_slice(&self.buffer[tag.0..tag.1]);
+                *tag = (start, buffer.len());
+            }
+            let position = buffer.len();
+            buffer.extend_from_slice(&self.buffer[tokenizer.i..]);
+
+            // positions move with the input they point at, the start of the document is gone
+            // once it is read
+            let read = tokenizer.i;
+            tokenizer.origin = match tokenizer.origin.checked_sub(read) {
+                Some(offset) if tokenizer.origin != usize::MAX => position + offset,
+                _ => usize::MAX,
+            };
+            tokenizer.scan = tokenizer
+                .scan
+                .map(|(start, end)| (start - read + position, end - read + position));
+            tokenizer.i = position;
+            self.cursor.position = position;
+            self.buffer = buffer;
+        }
+        self.tokenizer = Some(tokenizer);
+        result
+    }
+
+    /// Stops parsing on `error`, which is kept for the calls that follow.
+    fn stop<E>(&mut self, error: ParseXmlError) -> SaxError<E> {
+        self.error = Some(error.clone());
+        SaxError::Parse(error)
+    }
+}
+
+impl fmt::Debug for PushParser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PushParser")
+            .field("offset", &self.cursor.location.offset)
+            .field("buffered", &self.buffer.len())
+            .finish()
+    }
+}
+
+/// Handler building a document from its callbacks, e.g. to parse a document with a
+/// `PushParser` as the DOM parser does.
+#[derive(Debug)]
+pub struct DocumentBuilder {
+    document: Document<'static>,
+    builder: Builder<'static>,
+}
+
+impl DocumentBuilder {
+    /// Returns the document built so far.
+    pub fn into_document(self) -> Document<'static> {
+        self.document
+    }
+}
+
+impl Handler for DocumentBuilder {
+    type Error = Infallible;
+
+    fn start_element(
+        &mut self,
+        name: &str,
+        attributes: &[(&str, Cow<str>)],
+    ) -> Result<(), Infallible> {
+        let attributes = attributes.iter().map(|(name, value)| (*name, &**value));
+        self.builder.start_element(&mut Ready, name, attributes)
+    }
+
+    fn end_element(&mut self, _name: &str) -> Result<(), Infallible> {
+        self.builder.end_element();
+        Ok(())
+    }
+
+    fn characters(&mut self, text: &str) -> Result<(), Infallible> {
+        self.builder.text(&mut Ready, text)
+    }
+
+    fn cdata(&mut self, text: &str) -> Result<(), Infallible> {
+        self.builder.cdata(&mut Ready, text)
+    }
+
+    fn comment(&mut self, text: &str) -> Result<(), Infallible> {
+        self.builder.comment(&mut Ready, text)
+    }
+
+    fn processing_instruction(&mut self, target: &str, data: &str) -> Result<(), Infallible> {
+        self.builder
+            .processing_instruction(&mut Ready, target, data)
+    }
+
+    fn declaration(&mut self, attributes: &[(&str, &str)]) -> Result<(), Infallible> {
+        self.builder.declaration(&mut Ready, attributes);
+        Ok(())
+    }
+
+    fn doctype(&mut self, name: &str, value: &str) -> Result<(), Infallible> {
+        self.builder.doctype(&mut Ready, name, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Borrow;
+
+    use node::Attribute;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let result = Document::load_file("./xml/data1.xml");
+        assert!(result.is_ok());
+
+        assert_eq!(b'a', 97u8);
+    }
+
+    #[test]
+    fn bench_parse() {
+        let result = Document::load_file("./xml/large.xml");
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_parse_note_xml() {
+        let result = Document::load_file("./xml/note.xml");
 
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let doc = result.unwrap();
 
         let root = doc.root();
@@ -289,7 +3575,7 @@ mod tests {
             root.first_attribute().unwrap(),
             Attribute::new("id", "1").borrow()
         );
-        assert_eq!(root.parent().is_none(), true);
+        assert_eq!(*root.parent().unwrap().node_type(), NodeType::Document);
 
         let first = root.first_child().unwrap();
         assert_eq!(first.name(), "to");
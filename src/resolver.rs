@@ -0,0 +1,80 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+/// Provides the content of external entities, like `<!ENTITY logo SYSTEM "logo.xml">`, and of
+/// the external DTD subset named by `<!DOCTYPE note SYSTEM "note.dtd">`.
+///
+/// The parser never reads files or opens connections by itself: everything external goes
+/// through the resolver given to `Parser::entity_resolver`, and the default one,
+/// `NoExternalEntities`, refuses everything. External entities are never expanded in attribute
+/// values, where XML forbids them.
+pub trait EntityResolver {
+    /// Returns the content of the resource identified by `public_id` and `system_id`, or `None`
+    /// to leave it unresolved.
+    fn resolve(&self, public_id: Option<&str>, system_id: &str) -> Option<Vec<u8>>;
+}
+
+/// Resolver refusing all external access, which makes the parser safe from XXE attacks.
+///
+/// References to external entities are then kept as they are, or reported as
+/// `ErrorKind::UnresolvedEntity` in strict mode, and the external DTD subset is ignored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoExternalEntities;
+
+impl EntityResolver for NoExternalEntities {
+    fn resolve(&self, _public_id: Option<&str>, _system_id: &str) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Resolver reading system IDs as paths relative to a directory.
+///
+/// Only plain relative paths are resolved: absolute paths, URLs and paths with `..` are
+/// refused, so that a document cannot reach files outside of the directory.
+#[derive(Debug, Clone)]
+pub struct DirectoryResolver {
+    root: PathBuf,
+}
+
+impl DirectoryResolver {
+    pub fn new<P: Into<PathBuf>>(root: P) -> DirectoryResolver {
+        DirectoryResolver { root: root.into() }
+    }
+}
+
+impl EntityResolver for DirectoryResolver {
+    fn resolve(&self, _public_id: Option<&str>, system_id: &str) -> Option<Vec<u8>> {
+        let path = Path::new(system_id);
+        let plain = !system_id.contains(':')
+            && path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !plain {
+            return None;
+        }
+
+        let mut contents = Vec::new();
+        File::open(self.root.join(path))
+            .and_then(|mut file| file.read_to_end(&mut contents))
+            .ok()?;
+        Some(contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_resolver_test() {
+        let resolver = DirectoryResolver::new("./xml");
+        assert!(resolver.resolve(None, "note.xml").is_some());
+        assert!(resolver.resolve(None, "./note.xml").is_some());
+        assert!(resolver.resolve(None, "missing.xml").is_none());
+        assert!(resolver.resolve(None, "../Cargo.toml").is_none());
+        assert!(resolver.resolve(None, "/etc/passwd").is_none());
+        assert!(resolver.resolve(None, "file:///etc/passwd").is_none());
+        assert!(NoExternalEntities.resolve(None, "note.xml").is_none());
+    }
+}
@@ -0,0 +1,122 @@
+use parser::ParseXmlError;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+/// Receives the content of a document as `parser::parse_sax` or a `parser::PushParser` reads
+/// it, SAX style, instead of it being built into nodes.
+///
+/// Every method does nothing by default. Returning an error stops parsing, which then returns
+/// it as `SaxError::Handler`.
+///
+/// ```
+/// use dummy_xml::parser;
+/// use dummy_xml::sax::Handler;
+/// use std::borrow::Cow;
+/// use std::convert::Infallible;
+///
+/// struct Counter(usize);
+///
+/// impl Handler for Counter {
+///     type Error = Infallible;
+///
+///     fn start_element(&mut self, _: &str, _: &[(&str, Cow<str>)]) -> Result<(), Infallible> {
+///         self.0 += 1;
+///         Ok(())
+///     }
+/// }
+///
+/// let mut counter = Counter(0);
+/// parser::parse_sax(b"<a><b/><c/></a>", &mut counter).unwrap();
+/// assert_eq!(counter.0, 3);
+/// ```
+pub trait Handler {
+    type Error;
+
+    /// Called once before anything else.
+    fn start_document(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called once after the document is read, unless an error stopped parsing.
+    fn end_document(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for a start tag, with its attributes in the order they are written. An empty
+    /// element, `<a/>`, is a start tag followed by an end tag.
+    fn start_element(
+        &mut self,
+        _name: &str,
+        _attributes: &[(&str, Cow<str>)],
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn end_element(&mut self, _name: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for text. Adjacent text may come in several calls, e.g. around a comment.
+    fn characters(&mut self, _text: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for a CDATA section, passing its text to `characters` by default.
+    fn cdata(&mut self, text: &str) -> Result<(), Self::Error> {
+        self.characters(text)
+    }
+
+    /// Called for a comment, only read with `ParseOptions::COMMENTS`.
+    fn comment(&mut self, _text: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for a processing instruction, only read with `ParseOptions::PI`.
+    fn processing_instruction(&mut self, _target: &str, _data: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for the XML declaration, only read with `ParseOptions::DECLARATION`.
+    fn declaration(&mut self, _attributes: &[(&str, &str)]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for the document type declaration, only read with `ParseOptions::DOCTYPE`.
+    fn doctype(&mut self, _name: &str, _value: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Error returned by `parser::parse_sax` and `parser::PushParser`, when the document cannot be
+/// parsed or when the handler stopped parsing. Either error is its source, provided the one
+/// of the handler is an `Error`.
+#[derive(Debug)]
+pub enum SaxError<E> {
+    Parse(ParseXmlError),
+    Handler(E),
+}
+
+impl<E: fmt::Display> fmt::Display for SaxError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaxError::Parse(error) => error.fmt(f),
+            SaxError::Handler(error) => write!(f, "handler stopped parsing: {}", error),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for SaxError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SaxError::Parse(error) => Some(error),
+            SaxError::Handler(error) => Some(error),
+        }
+    }
+}
+
+impl<E> From<ParseXmlError> for SaxError<E> {
+    fn from(error: ParseXmlError) -> SaxError<E> {
+        SaxError::Parse(error)
+    }
+}
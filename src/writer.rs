@@ -1,24 +1,100 @@
-use node::{Attribute, Node};
+use node::{Attribute, Node, NodeType};
+use parser::Document;
 use std::fmt::Write;
 
+/// Writes a whole document: its XML declaration and document type declaration if it has them,
+/// the document element and the comments and processing instructions around it.
+pub fn write_document(src: &Document, des: &mut String) {
+    write_node(src.node(), des);
+}
+
 pub fn write(src: &Node, des: &mut String) {
-    write!(des, "<{}", src.name()).expect("Error occurred while trying to write in String");
-    src.first_attribute().map(|attr| write_attribute(attr, des));
-    write!(des, ">").expect("Error occurred while trying to write in String");
-    src.first_child().map(|node| write(node, des));
-    write!(des, "</{}>", src.name()).expect("Error occurred while trying to write in String");
-    src.next_sibling().map(|node| write(node, des));
+    write_node(src, des);
+    if let Some(node) = src.next_sibling() {
+        write(node, des);
+    }
+}
+
+/// Writes `src` and its children, but not its siblings.
+fn write_node(src: &Node, des: &mut String) {
+    match *src.node_type() {
+        NodeType::PcData => write_escaped(src.value(), None, des),
+        NodeType::Comment => write!(des, "<!--{}-->", src.value())
+            .expect("Error occurred while trying to write in String"),
+        NodeType::ProcessingInstruction if src.value().is_empty() => {
+            write!(des, "<?{}?>", src.name())
+                .expect("Error occurred while trying to write in String")
+        }
+        NodeType::ProcessingInstruction => write!(des, "<?{} {}?>", src.name(), src.value())
+            .expect("Error occurred while trying to write in String"),
+        NodeType::Doctype => write!(des, "<!DOCTYPE {}>", src.value())
+            .expect("Error occurred while trying to write in String"),
+        NodeType::Document => {
+            if let Some(node) = src.first_child() {
+                write(node, des);
+            }
+        }
+        NodeType::Declaration => {
+            write!(des, "<?xml").expect("Error occurred while trying to write in String");
+            if let Some(attr) = src.first_attribute() {
+                write_attribute(attr, des);
+            }
+            write!(des, "?>").expect("Error occurred while trying to write in String");
+        }
+        // "]]>" cannot appear in a CDATA section, so it is split across two sections
+        NodeType::CData => write!(
+            des,
+            "<![CDATA[{}]]>",
+            src.value().replace("]]>", "]]]]><![CDATA[>")
+        )
+        .expect("Error occurred while trying to write in String"),
+        _ => {
+            write!(des, "<{}", src.name()).expect("Error occurred while trying to write in String");
+            if let Some(attr) = src.first_attribute() {
+                write_attribute(attr, des);
+            }
+            write!(des, ">").expect("Error occurred while trying to write in String");
+            if let Some(node) = src.first_child() {
+                write(node, des);
+            }
+            write!(des, "</{}>", src.name())
+                .expect("Error occurred while trying to write in String");
+        }
+    }
 }
 
 fn write_attribute(src: &Attribute, des: &mut String) {
-    if src.value().contains("'") {
-        write!(des, " {}=\"{}\"", src.name(), src.value())
-            .expect("Error occurred while trying to write in String");
+    if src.value().contains('\'') {
+        write!(des, " {}=\"", src.name()).expect("Error occurred while trying to write in String");
+        write_escaped(src.value(), Some('"'), des);
+        des.push('"');
     } else {
-        write!(des, " {}='{}'", src.name(), src.value())
-            .expect("Error occurred while trying to write in String");
+        write!(des, " {}='", src.name()).expect("Error occurred while trying to write in String");
+        write_escaped(src.value(), Some('\''), des);
+        des.push('\'');
+    }
+    if let Some(attr) = src.next_attribute() {
+        write_attribute(attr, des);
+    }
+}
+
+/// Writes `value` escaping the characters that cannot appear as-is in text, or inside `quote`
+/// for an attribute value.
+fn write_escaped(value: &str, quote: Option<char>, des: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => des.push_str("&amp;"),
+            '<' => des.push_str("&lt;"),
+            // "]]>" is not allowed in text
+            '>' => des.push_str("&gt;"),
+            // whitespace written as is would be normalized when parsed again
+            '\r' => des.push_str("&#13;"),
+            '\n' if quote.is_some() => des.push_str("&#10;"),
+            '\t' if quote.is_some() => des.push_str("&#9;"),
+            '"' if quote == Some('"') => des.push_str("&quot;"),
+            _ => des.push(c),
+        }
     }
-    src.next_attribute().map(|attr| write_attribute(attr, des));
 }
 
 #[cfg(test)]
@@ -33,4 +109,87 @@ mod tests {
         write(result.unwrap().root(), &mut txt);
         assert_eq!(txt, "<parent><child1 name='go'></child1></parent>");
     }
+
+    #[test]
+    fn write_escaped_attribute_test() {
+        let result = parser::parse_str("<a b='&lt;&amp;&quot;' c=\"'\"></a>");
+        let mut txt = String::new();
+        write(result.unwrap().root(), &mut txt);
+        assert_eq!(txt, "<a b='&lt;&amp;\"' c=\"'\"></a>");
+    }
+
+    #[test]
+    fn write_escaped_whitespace_test() {
+        let result = parser::parse_str("<a b='x&#13;&#10;&#9;'>\r\ny&#13;\t</a>");
+        let mut txt = String::new();
+        write(result.unwrap().root(), &mut txt);
+        assert_eq!(txt, "<a b='x&#13;&#10;&#9;'>\ny&#13;\t</a>");
+    }
+
+    #[test]
+    fn write_escaped_text_test() {
+        let result = parser::parse_str("<a b='&gt;'>]]&gt;</a>");
+        let mut txt = String::new();
+        write(result.unwrap().root(), &mut txt);
+        assert_eq!(txt, "<a b='&gt;'>]]&gt;</a>");
+    }
+
+    #[test]
+    fn write_node_types_test() {
+        let result = parser::parse_with_options(
+            b"<a>x &lt; y<!-- a < b --><b/><![CDATA[<&>]]><?pi  a < b?><?pi?></a>",
+            parser::ParseOptions::FULL,
+        );
+        let mut txt = String::new();
+        write(result.unwrap().root(), &mut txt);
+        assert_eq!(
+            txt,
+            "<a>x &lt; y<!-- a < b --><b></b><![CDATA[<&>]]><?pi a < b?><?pi?></a>"
+        );
+    }
+
+    #[test]
+    fn write_fragment_test() {
+        let result = parser::parse_fragment(b"x <a/><b>y</b> z");
+        let mut txt = String::new();
+        write(result.unwrap().root(), &mut txt);
+        assert_eq!(txt, "x <a></a><b>y</b> z");
+    }
+
+    #[test]
+    fn write_document_test() {
+        let contents = "<?xml version=\"1.0\" encoding=\"UTF-8\"?><!-- c --><a/><?pi?>";
+        let mut document =
+            parser::parse_with_options(contents.as_bytes(), parser::ParseOptions::FULL).unwrap();
+        let mut txt = String::new();
+        write_document(&document, &mut txt);
+        assert_eq!(
+            txt,
+            "<?xml version='1.0' encoding='UTF-8'?><!-- c --><a></a><?pi?>"
+        );
+
+        document.set_standalone(true).set_encoding("ISO-8859-1");
+        let mut txt = String::new();
+        write_document(&document, &mut txt);
+        assert_eq!(
+            txt,
+            "<?xml version='1.0' encoding='ISO-8859-1' standalone='yes'?><!-- c --><a></a><?pi?>"
+        );
+
+        let mut document = parser::parse_str("<!-- c --><a/>").unwrap();
+        document.set_encoding("UTF-8");
+        let mut txt = String::new();
+        write_document(&document, &mut txt);
+        assert_eq!(txt, "<?xml version='1.0' encoding='UTF-8'?><a></a>");
+    }
+
+    #[test]
+    fn write_doctype_test() {
+        let contents = "<!DOCTYPE a [ <!ELEMENT a (#PCDATA)> ]><a>x</a>";
+        let document =
+            parser::parse_with_options(contents.as_bytes(), parser::ParseOptions::FULL).unwrap();
+        let mut txt = String::new();
+        write_document(&document, &mut txt);
+        assert_eq!(txt, contents);
+    }
 }
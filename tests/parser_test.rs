@@ -1,18 +1,378 @@
 extern crate dummy_xml;
 
-use std::fs::File;
-use std::io::prelude::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
 
+use dummy_xml::encoding::Encoding;
+use dummy_xml::node::NodeType;
 use dummy_xml::parser;
+use dummy_xml::parser::{
+    Document, DuplicateAttributes, ErrorKind, Event, LoadError, ParseOptions, ParseXmlError,
+    Parser, Reader,
+};
+use dummy_xml::resolver::EntityResolver;
+use dummy_xml::sax::{Handler, SaxError};
+use dummy_xml::writer;
+use std::borrow::Cow;
 
 #[test]
 fn parse_test() {
-    let mut f = File::open("./xml/large.xml").expect("file not found");
-    let mut contents = String::new();
-    let result = f.read_to_string(&mut contents);
-    assert_eq!(result.is_ok(), true);
+    let result = Document::load_file("./xml/large.xml");
+    assert!(result.is_ok());
+}
+
+#[test]
+fn load_documents() {
+    let document = Document::load_reader(io::Cursor::new("<a x='1'>&lt;</a>")).unwrap();
+    assert_eq!(document.root().attribute("x"), Some("1"));
+    assert_eq!(document.root().first_child().unwrap().value(), "<");
+
+    let document = {
+        let contents = b"<a><b/></a>".to_vec();
+        Document::load_bytes(&contents).unwrap()
+    };
+    assert_eq!(document.root().first_child().unwrap().name(), "b");
+
+    match Document::load_file("./xml/missing.xml") {
+        Err(LoadError::Io(error)) => assert_eq!(error.kind(), io::ErrorKind::NotFound),
+        other => panic!("{:?}", other),
+    }
+    match Document::load_reader(io::Cursor::new("<a>")) {
+        Err(LoadError::Parse(error)) => assert_eq!(error.kind(), ErrorKind::UnclosedElement),
+        other => panic!("{:?}", other),
+    }
+
+    // the error of the parser is the source of the one returned
+    let error = Document::load_bytes(b"<a>").unwrap_err();
+    let source = error
+        .source()
+        .and_then(|e| e.downcast_ref::<ParseXmlError>());
+    assert_eq!(
+        source.map(ParseXmlError::kind),
+        Some(ErrorKind::UnclosedElement)
+    );
+    let parser = Parser::new();
+    let mut push_parser = parser.push_parser();
+    let mut builder = parser.document_builder();
+    push_parser.feed(b"<a>", &mut builder).unwrap();
+    let error = push_parser.finish(&mut builder).unwrap_err();
+    let source = error
+        .source()
+        .and_then(|e| e.downcast_ref::<ParseXmlError>());
+    assert_eq!(
+        source.map(ParseXmlError::kind),
+        Some(ErrorKind::UnclosedElement)
+    );
+}
+
+#[test]
+fn read_events() {
+    let contents = "<?xml version='1.0'?><!DOCTYPE a><a x='&lt;' y='2'>t<![CDATA[c]]><b/>\
+                    <!--n--><?pi d?></a>";
+    let events: Vec<Event> = Parser::new()
+        .options(ParseOptions::FULL)
+        .reader(contents.as_bytes())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        events,
+        vec![
+            Event::Declaration {
+                attributes: vec![("version", "1.0")],
+            },
+            Event::Doctype {
+                name: "a",
+                value: "a".into(),
+            },
+            Event::StartElement {
+                name: "a",
+                attributes: vec![("x", "<".into()), ("y", "2".into())],
+            },
+            Event::Text("t".into()),
+            Event::CData("c".into()),
+            Event::StartElement {
+                name: "b",
+                attributes: vec![],
+            },
+            Event::EndElement { name: "b" },
+            Event::Comment("n".into()),
+            Event::PI {
+                target: "pi",
+                data: "d".into(),
+            },
+            Event::EndElement { name: "a" },
+        ]
+    );
+
+    // comments are left out by default, and so is whitespace between elements
+    let events: Vec<Event> = Reader::new(b"<a>\n  <!--n--><b/>\n</a>")
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(events.len(), 4);
+
+    let mut reader = Reader::new(b"<a><b></a>");
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_ok());
+    let error = reader.next().unwrap().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::MismatchedCloseTag);
+    assert_eq!(error.offset(), 6);
+    assert!(reader.next().is_none());
+
+    let error = Parser::new()
+        .options(ParseOptions::DEFAULT | ParseOptions::STRICT)
+        .reader(b"<a x='&bad;'/>")
+        .next()
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UndefinedEntity);
+
+    // the reader does not transcode, so only reads UTF-8
+    let mut reader = Reader::new(b"\xFF\xFE<\0a\0/\0>\0");
+    let error = reader.next().unwrap().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnsupportedEncoding);
+    assert!(reader.next().is_none());
+    let mut reader = Parser::new().encoding(Encoding::Latin1).reader(b"<a/>");
+    assert!(reader.next().unwrap().is_err());
+}
+
+/// Records the callbacks it receives, stopping at the element named `stop`.
+struct Recorder(Vec<String>);
+
+impl Handler for Recorder {
+    type Error = String;
+
+    fn start_document(&mut self) -> Result<(), String> {
+        self.0.push("start".to_string());
+        Ok(())
+    }
+
+    fn end_document(&mut self) -> Result<(), String> {
+        self.0.push("end".to_string());
+        Ok(())
+    }
+
+    fn start_element(&mut self, name: &str, attributes: &[(&str, Cow<str>)]) -> Result<(), String> {
+        if name == "stop" {
+            return Err(format!("stopped after {} callbacks", self.0.len()));
+        }
+        let attributes: Vec<String> = attributes
+            .iter()
+            .map(|(name, value)| format!(" {}={}", name, value))
+            .collect();
+        self.0.push(format!("<{}{}>", name, attributes.concat()));
+        Ok(())
+    }
+
+    fn end_element(&mut self, name: &str) -> Result<(), String> {
+        self.0.push(format!("</{}>", name));
+        Ok(())
+    }
+
+    fn characters(&mut self, text: &str) -> Result<(), String> {
+        self.0.push(text.to_string());
+        Ok(())
+    }
+
+    fn comment(&mut self, text: &str) -> Result<(), String> {
+        self.0.push(format!("<!--{}-->", text));
+        Ok(())
+    }
+}
+
+#[test]
+fn parse_sax() {
+    let contents = "<a x='&amp;'>t<![CDATA[<c>]]><!--n--><b/></a>";
+    let mut recorder = Recorder(Vec::new());
+    Parser::new()
+        .options(ParseOptions::FULL)
+        .parse_sax(contents.as_bytes(), &mut recorder)
+        .unwrap();
+    assert_eq!(
+        recorder.0,
+        ["start", "<a x=&>", "t", "<c>", "<!--n-->", "<b>", "</b>", "</a>", "end"]
+    );
+
+    let utf16: Vec<u8> = "<a>\u{e9}</a>"
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes().to_vec())
+        .collect();
+    let mut recorder = Recorder(Vec::new());
+    parser::parse_sax(&utf16, &mut recorder).unwrap();
+    assert_eq!(recorder.0[2], "\u{e9}");
+
+    let mut recorder = Recorder(Vec::new());
+    match parser::parse_sax(b"<a><stop/><b></a>", &mut recorder) {
+        Err(SaxError::Handler(error)) => assert_eq!(error, "stopped after 2 callbacks"),
+        other => panic!("{:?}", other),
+    }
+
+    let mut recorder = Recorder(Vec::new());
+    match parser::parse_sax(b"<a>t</b>", &mut recorder) {
+        Err(SaxError::Parse(error)) => assert_eq!(error.kind(), ErrorKind::MismatchedCloseTag),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(recorder.0, ["start", "<a>", "t"]);
+}
+
+#[test]
+fn push_parser() {
+    let contents =
+        "<?xml version='1.0'?>\n<a x='&lt;\u{e9}'>t&amp;<![CDATA[c]]>\r\n<b/><!--n--></a>";
+    let mut parser = Parser::new();
+    parser.options(ParseOptions::FULL);
+    let mut expected = String::new();
+    writer::write_document(&parser.parse(contents.as_bytes()).unwrap(), &mut expected);
+
+    // chunks ending in the middle of tags, references and characters
+    for size in 1..contents.len() {
+        let mut push_parser = parser.push_parser();
+        let mut builder = parser.document_builder();
+        for chunk in contents.as_bytes().chunks(size) {
+            push_parser.feed(chunk, &mut builder).unwrap();
+        }
+        push_parser.finish(&mut builder).unwrap();
+        let mut written = String::new();
+        writer::write_document(&builder.into_document(), &mut written);
+        assert_eq!(written, expected);
+    }
+
+    let mut push_parser = Parser::new().push_parser();
+    let mut recorder = Recorder(Vec::new());
+    push_parser.feed(b"<a><b>te", &mut recorder).unwrap();
+    assert_eq!(recorder.0, ["start", "<a>", "<b>"]);
+    push_parser.feed(b"xt</b>\n<c", &mut recorder).unwrap();
+    assert_eq!(recorder.0, ["start", "<a>", "<b>", "text", "</b>"]);
+    match push_parser.finish(&mut recorder) {
+        Err(SaxError::Parse(error)) => {
+            assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+            assert_eq!((error.offset(), error.line(), error.column()), (17, 2, 3));
+        }
+        other => panic!("{:?}", other),
+    }
+
+    let mut push_parser = Parser::new().push_parser();
+    let mut recorder = Recorder(Vec::new());
+    push_parser.feed(b"<a>\n<b></", &mut recorder).unwrap();
+    match push_parser.feed(b"a>", &mut recorder) {
+        Err(SaxError::Parse(error)) => {
+            assert_eq!(error.kind(), ErrorKind::MismatchedCloseTag);
+            assert_eq!((error.offset(), error.line(), error.column()), (7, 2, 4));
+        }
+        other => panic!("{:?}", other),
+    }
+    // the error is returned again once parsing stopped on it
+    let expected = parser::parse_str("<a>\n<b></a>").unwrap_err();
+    match push_parser.feed(b"</b>", &mut recorder) {
+        Err(SaxError::Parse(error)) => assert_eq!(error, expected),
+        other => panic!("{:?}", other),
+    }
+    match push_parser.finish(&mut recorder) {
+        Err(SaxError::Parse(error)) => assert_eq!(error, expected),
+        other => panic!("{:?}", other),
+    }
+
+    // parsing resumes after the event refused by the handler
+    let mut push_parser = Parser::new().push_parser();
+    let mut recorder = Recorder(Vec::new());
+    match push_parser.feed(b"<a><stop/>t", &mut recorder) {
+        Err(SaxError::Handler(error)) => assert_eq!(error, "stopped after 2 callbacks"),
+        other => panic!("{:?}", other),
+    }
+    push_parser.feed(b"</a>", &mut recorder).unwrap();
+    push_parser.finish(&mut recorder).unwrap();
+    assert_eq!(recorder.0, ["start", "<a>", "t", "</a>", "end"]);
+
+    // start tags and document type declarations are read once their '>' is in, the ones in
+    // their values, comments and processing instructions aside
+    let contents = "<!DOCTYPE a [<!-- ' ]> --><?p '?><!ENTITY x \"]>\">]>\
+                    <a x='>' y=\"&x;'>\"><b z='/>'/></a>";
+    let mut parser = Parser::new();
+    parser.options(ParseOptions::FULL);
+    let mut expected = String::new();
+    writer::write_document(&parser.parse(contents.as_bytes()).unwrap(), &mut expected);
+    for size in 1..contents.len() {
+        let mut push_parser = parser.push_parser();
+        let mut builder = parser.document_builder();
+        for chunk in contents.as_bytes().chunks(size) {
+            push_parser.feed(chunk, &mut builder).unwrap();
+        }
+        push_parser.finish(&mut builder).unwrap();
+        let mut written = String::new();
+        writer::write_document(&builder.into_document(), &mut written);
+        assert_eq!(written, expected);
+    }
+    let mut contents = "<!DOCTYPE a [".to_string();
+    for i in 0..10_000 {
+        contents.push_str(&format!("<!ENTITY e{} '>'>", i));
+    }
+    contents.push_str("]><a");
+    for i in 0..10_000 {
+        contents.push_str(&format!(" a{}='>'", i));
+    }
+    contents.push_str("/>");
+    let mut push_parser = Parser::new().push_parser();
+    let mut recorder = Recorder(Vec::new());
+    for chunk in contents.as_bytes().chunks(16) {
+        push_parser.feed(chunk, &mut recorder).unwrap();
+    }
+    push_parser.finish(&mut recorder).unwrap();
+    assert_eq!(recorder.0.len(), 4);
+
+    // a document type declaration is only read again while it may be cut short
+    let mut push_parser = Parser::new().push_parser();
+    let mut recorder = Recorder(Vec::new());
+    push_parser.feed(b"<!DOCTYPE a SYS", &mut recorder).unwrap();
+    push_parser
+        .feed(b"TEM 'a.dtd'><a/>", &mut recorder)
+        .unwrap();
+    push_parser.finish(&mut recorder).unwrap();
+    let mut push_parser = Parser::new().push_parser();
+    match push_parser.feed(b"<!DOCTYPE a BAD>", &mut recorder) {
+        Err(SaxError::Parse(error)) => assert_eq!(error.kind(), ErrorKind::BadDoctype),
+        other => panic!("{:?}", other),
+    }
+
+    // what is read is dropped, outside of the root element too
+    let mut push_parser = Parser::new().push_parser();
+    let mut recorder = Recorder(Vec::new());
+    for _ in 0..100 {
+        push_parser.feed(b"<!-- before -->", &mut recorder).unwrap();
+    }
+    push_parser.feed(b"<a/>", &mut recorder).unwrap();
+    for _ in 0..100 {
+        push_parser.feed(b"<!-- after -->", &mut recorder).unwrap();
+    }
+    assert!(format!("{:?}", push_parser).ends_with("buffered: 0 }"));
+    push_parser.finish(&mut recorder).unwrap();
 
-    let _ = parser::parse(contents.as_bytes());
+    // only UTF-8 is read, which takes the XML declaration to tell
+    let mut push_parser = Parser::new().push_parser();
+    let mut recorder = Recorder(Vec::new());
+    push_parser
+        .feed(b"<?xml version='1.0' ", &mut recorder)
+        .unwrap();
+    match push_parser.feed(b"encoding='ISO-8859-1'?><a/>", &mut recorder) {
+        Err(SaxError::Parse(error)) => assert_eq!(error.kind(), ErrorKind::UnsupportedEncoding),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(recorder.0, ["start"]);
+    let mut push_parser = Parser::new().encoding(Encoding::Utf16Le).push_parser();
+    match push_parser.feed(b"<", &mut recorder) {
+        Err(SaxError::Parse(error)) => assert_eq!(error.kind(), ErrorKind::UnsupportedEncoding),
+        other => panic!("{:?}", other),
+    }
+
+    let mut push_parser = Parser::new().push_parser();
+    let mut builder = Parser::new().document_builder();
+    push_parser.feed(b"<a><b></b>", &mut builder).unwrap();
+    match push_parser.finish(&mut builder) {
+        Err(SaxError::Parse(error)) => {
+            assert_eq!(error.kind(), ErrorKind::UnclosedElement);
+            assert_eq!(error.offset(), 0);
+        }
+        other => panic!("{:?}", other),
+    }
 }
 
 #[test]
@@ -43,3 +403,919 @@ fn parse_negative_1() {
     let result = parser::parse_str("<parent><child1 ");
     assert!(result.is_err());
 }
+
+#[test]
+fn parse_error_position() {
+    let error = parser::parse_str("<parent>\n  <child1 name=go/>\n</parent>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadAttribute);
+    assert_eq!(error.offset(), 24);
+    assert_eq!(error.line(), 2);
+    assert_eq!(error.column(), 16);
+    assert_eq!(
+        error.to_string(),
+        "bad attribute syntax at line 2, column 16 (byte 24)"
+    );
+}
+
+#[test]
+fn parse_error_truncated() {
+    let contents = "<parent><child1 ";
+    let error = parser::parse_str(contents).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(error.offset(), contents.len());
+}
+
+#[test]
+fn parse_error_invalid_name() {
+    let error = parser::parse_str("<parent><1child/></parent>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidName);
+    assert_eq!(error.offset(), 9);
+}
+
+#[test]
+fn parse_truncated_input_never_panics() {
+    let contents =
+        "<parent id='1'><child1 name=\"go\"/><!-- note --><child2>rust</child2></parent>";
+    for end in 0..contents.len() {
+        let error = parser::parse_str(&contents[..end]).unwrap_err();
+        assert!(
+            error.kind() == ErrorKind::UnexpectedEof || error.kind() == ErrorKind::UnclosedElement
+        );
+    }
+    assert!(parser::parse_str(contents).is_ok());
+}
+
+#[test]
+fn parse_self_closing_root() {
+    let document = parser::parse_str("<parent/>\n").unwrap();
+    assert_eq!(document.root().name(), "parent");
+}
+
+#[test]
+fn parse_mismatched_close_tag() {
+    let error = parser::parse_str("<a><b></a></b>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::MismatchedCloseTag);
+    assert_eq!(error.offset(), 6);
+}
+
+#[test]
+fn parse_unexpected_close_tag() {
+    let error = parser::parse_str("<a></a></b>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedCloseTag);
+    assert_eq!(error.offset(), 7);
+}
+
+#[test]
+fn parse_unclosed_element() {
+    let error = parser::parse_str("<a>\n  <b>text</b>\n  <c>\n").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnclosedElement);
+    assert_eq!(error.line(), 3);
+    assert_eq!(error.column(), 3);
+}
+
+#[test]
+fn parse_escapes() {
+    let document =
+        parser::parse_str("<a title='&lt;&#x20AC;&#36;&gt;'>fish &amp; chips &quot;&apos;</a>")
+            .unwrap();
+    let root = document.root();
+    assert_eq!(root.attribute("title"), Some("<€$>"));
+    assert_eq!(root.first_child().unwrap().value(), "fish & chips \"'");
+}
+
+#[test]
+fn parse_escapes_disabled() {
+    let document =
+        parser::parse_with_options("<a b='&lt;'>&amp;</a>".as_bytes(), ParseOptions::empty())
+            .unwrap();
+    let root = document.root();
+    assert_eq!(root.attribute("b"), Some("&lt;"));
+    assert_eq!(root.first_child().unwrap().value(), "&amp;");
+}
+
+#[test]
+fn parse_bad_references() {
+    let contents = "<a>AT&T &nbsp; &#xD800; &amp</a>";
+    let document = parser::parse_str(contents).unwrap();
+    assert_eq!(
+        document.root().first_child().unwrap().value(),
+        "AT&T &nbsp; &#xD800; &amp"
+    );
+
+    let strict = ParseOptions::ESCAPES | ParseOptions::STRICT;
+    let error = parser::parse_with_options(contents.as_bytes(), strict).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadReference);
+    assert_eq!(error.offset(), 5);
+
+    let error = parser::parse_with_options("<a>&nbsp;</a>".as_bytes(), strict).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UndefinedEntity);
+    assert_eq!(error.offset(), 3);
+}
+
+#[test]
+fn parse_options_presets() {
+    assert_eq!(ParseOptions::default(), ParseOptions::DEFAULT);
+    assert!(ParseOptions::DEFAULT.contains(ParseOptions::ESCAPES | ParseOptions::EOL));
+    assert!(!ParseOptions::DEFAULT.contains(ParseOptions::COMMENTS));
+    assert!(ParseOptions::FULL.contains(ParseOptions::DEFAULT | ParseOptions::COMMENTS));
+    assert_eq!(ParseOptions::MINIMAL, ParseOptions::empty());
+
+    let mut options = ParseOptions::FULL;
+    options.remove(ParseOptions::PI | ParseOptions::DOCTYPE);
+    assert!(!options.contains(ParseOptions::PI));
+    assert!(options.contains(ParseOptions::DECLARATION));
+}
+
+#[test]
+fn parse_whitespace_pcdata() {
+    let contents = "<a>\n  <b>  x  </b>\n</a>";
+
+    let document = parser::parse_str(contents).unwrap();
+    let b = document.root().first_child().unwrap();
+    assert_eq!(b.name(), "b");
+    assert_eq!(b.first_child().unwrap().value(), "  x  ");
+    assert!(b.next_sibling().is_none());
+
+    let options = ParseOptions::DEFAULT | ParseOptions::WS_PCDATA | ParseOptions::TRIM_PCDATA;
+    let document = parser::parse_with_options(contents.as_bytes(), options).unwrap();
+    let indent = document.root().first_child().unwrap();
+    assert_eq!(indent.value(), "");
+    let b = indent.next_sibling().unwrap();
+    assert_eq!(b.first_child().unwrap().value(), "x");
+}
+
+#[test]
+fn parse_whitespace_modes() {
+    let contents = "<a> \t x \n\n y </a>";
+    let options = ParseOptions::DEFAULT | ParseOptions::COLLAPSE_PCDATA;
+    let document = parser::parse_with_options(contents.as_bytes(), options).unwrap();
+    assert_eq!(document.root().first_child().unwrap().value(), " x y ");
+
+    let options = options | ParseOptions::TRIM_PCDATA;
+    let document = parser::parse_with_options(contents.as_bytes(), options).unwrap();
+    assert_eq!(document.root().first_child().unwrap().value(), "x y");
+
+    let contents =
+        "<a>\n <pre xml:space='preserve'>\n <b>  x  y </b> <c xml:space='default'> z </c>\
+                    </pre>\n <d> w </d>\n</a>";
+    let document = parser::parse_with_options(contents.as_bytes(), options).unwrap();
+    let pre = document.root().first_child().unwrap();
+    assert_eq!(pre.name(), "pre");
+    let indent = pre.first_child().unwrap();
+    assert_eq!(indent.value(), "\n ");
+    let b = indent.next_sibling().unwrap();
+    assert_eq!(b.first_child().unwrap().value(), "  x  y ");
+    let c = b.next_sibling().unwrap().next_sibling().unwrap();
+    assert_eq!(c.first_child().unwrap().value(), "z");
+    let d = pre.next_sibling().unwrap();
+    assert_eq!(d.name(), "d");
+    assert_eq!(d.first_child().unwrap().value(), "w");
+}
+
+#[test]
+fn parse_eol() {
+    let contents = "<a b='1\r\n2\r3'>x\r\ny\rz&#13;</a>";
+
+    let document = parser::parse_str(contents).unwrap();
+    let root = document.root();
+    assert_eq!(root.attribute("b"), Some("1 2 3"));
+    assert_eq!(root.first_child().unwrap().value(), "x\ny\nz\r");
+
+    let options = ParseOptions::DEFAULT & !ParseOptions::WCONV_ATTRIBUTE;
+    let document = parser::parse_with_options(contents.as_bytes(), options).unwrap();
+    assert_eq!(document.root().attribute("b"), Some("1\n2\n3"));
+
+    let document = parser::parse_with_options(contents.as_bytes(), ParseOptions::MINIMAL).unwrap();
+    let root = document.root();
+    assert_eq!(root.attribute("b"), Some("1\r\n2\r3"));
+    assert_eq!(root.first_child().unwrap().value(), "x\r\ny\rz&#13;");
+
+    let contents = "<!DOCTYPE a [\r\n<!ENTITY e 'x\r\ny&#13;'>\r\n]>\r\n\
+                    <a>&e;<![CDATA[\r\n]]><?pi a\r\nb?><!--\r--></a>";
+    let document = parser::parse_with_options(contents.as_bytes(), ParseOptions::FULL).unwrap();
+    assert_eq!(
+        document.doctype().unwrap().value(),
+        "a [\n<!ENTITY e 'x\ny&#13;'>\n]"
+    );
+    let text = document.root().first_child().unwrap();
+    assert_eq!(text.value(), "x\ny\r");
+    let cdata = text.next_sibling().unwrap();
+    assert_eq!(cdata.value(), "\n");
+    let pi = cdata.next_sibling().unwrap();
+    assert_eq!(pi.value(), "a\nb");
+    assert_eq!(pi.next_sibling().unwrap().value(), "\n");
+
+    let options = ParseOptions::FULL & !ParseOptions::EOL;
+    let document = parser::parse_with_options(contents.as_bytes(), options).unwrap();
+    let text = document.root().first_child().unwrap();
+    assert_eq!(text.value(), "x\r\ny\r");
+    assert_eq!(text.next_sibling().unwrap().value(), "\r\n");
+}
+
+#[test]
+fn parse_attributes() {
+    let contents = "<a x = \"1\" y\n=\t'\t2\r\n &#10; 3 '/>";
+
+    let document = parser::parse_str(contents).unwrap();
+    let root = document.root();
+    assert_eq!(root.attribute("x"), Some("1"));
+    assert_eq!(root.attribute("y"), Some(" 2  \n 3 "));
+
+    let options = ParseOptions::DEFAULT | ParseOptions::WNORM_ATTRIBUTE;
+    let document = parser::parse_with_options(contents.as_bytes(), options).unwrap();
+    assert_eq!(document.root().attribute("y"), Some("2 \n 3"));
+
+    let error = parser::parse_str("<a x=1/>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadAttribute);
+    assert_eq!(error.offset(), 5);
+    let error = parser::parse_str("<a x/>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadAttribute);
+    let error = parser::parse_str("<a x='1'y='2'/>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadAttribute);
+    assert_eq!(error.offset(), 8);
+    let error = parser::parse_str("<a 1x='1'/>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidName);
+
+    let options = ParseOptions::DEFAULT | ParseOptions::WNORM_ATTRIBUTE | ParseOptions::STRICT;
+    let error = parser::parse_with_options(b"<a x='  1  &bad; <'/>", options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadAttribute);
+    assert_eq!(error.offset(), 17);
+    let error = parser::parse_with_options(b"<a x='  1  &bad;'/>", options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UndefinedEntity);
+    assert_eq!(error.offset(), 11);
+}
+
+#[test]
+fn parse_duplicate_attributes() {
+    let contents = "<a x='1' y='2' x='3'/>";
+
+    let document = parser::parse_str(contents).unwrap();
+    let x = document.root().first_attribute().unwrap();
+    assert_eq!((x.name(), x.value()), ("x", "1"));
+    let y = x.next_attribute().unwrap();
+    assert_eq!((y.name(), y.value()), ("y", "2"));
+    assert!(y.next_attribute().is_none());
+
+    let document = Parser::new()
+        .duplicate_attributes(DuplicateAttributes::KeepLast)
+        .parse(contents.as_bytes())
+        .unwrap();
+    let x = document.root().first_attribute().unwrap();
+    assert_eq!((x.name(), x.value()), ("x", "3"));
+    assert!(x.next_attribute().unwrap().next_attribute().is_none());
+
+    let options = ParseOptions::DEFAULT | ParseOptions::STRICT;
+    let error = parser::parse_with_options(contents.as_bytes(), options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::DuplicateAttribute);
+    assert_eq!(error.offset(), 15);
+
+    // many attributes are looked up by name, not one by one
+    let mut contents = String::from("<a");
+    for i in 0..50_000 {
+        contents.push_str(&format!(" a{}='{}'", i, i));
+    }
+    let duplicate = contents.len() + 1;
+    contents.push_str(" a7='x' a49999='y'/>");
+    let document = Parser::new()
+        .duplicate_attributes(DuplicateAttributes::KeepLast)
+        .parse(contents.as_bytes())
+        .unwrap();
+    assert_eq!(document.root().attribute("a7"), Some("x"));
+    assert_eq!(document.root().attribute("a49999"), Some("y"));
+    let error = parser::parse_with_options(contents.as_bytes(), options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::DuplicateAttribute);
+    assert_eq!(error.offset(), duplicate);
+}
+
+#[test]
+fn parse_fragment() {
+    let document = parser::parse_fragment(b"<b>bold</b> and <i>italic</i><!-- c -->").unwrap();
+    let root = document.root();
+    assert_eq!(*root.node_type(), NodeType::Document);
+    let b = root.first_child().unwrap();
+    assert_eq!(b.name(), "b");
+    let text = b.next_sibling().unwrap();
+    assert_eq!(text.value(), " and ");
+    let i = text.next_sibling().unwrap();
+    assert_eq!(i.name(), "i");
+    assert!(i.next_sibling().is_none());
+
+    let options = ParseOptions::FULL | ParseOptions::FRAGMENT;
+    let document = parser::parse_with_options(b"text &amp; <?pi?> more", options).unwrap();
+    let text = document.root().first_child().unwrap();
+    assert_eq!(text.value(), "text & ");
+    let pi = text.next_sibling().unwrap();
+    assert_eq!(*pi.node_type(), NodeType::ProcessingInstruction);
+    assert_eq!(pi.next_sibling().unwrap().value(), " more");
+
+    let document = parser::parse_fragment(b"").unwrap();
+    assert!(document.root().first_child().is_none());
+
+    let error = parser::parse_fragment(b"<a></a></b>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedCloseTag);
+    let error = parser::parse_fragment(b"<a>text").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnclosedElement);
+}
+
+#[test]
+fn parse_document_node() {
+    let document = Parser::new()
+        .options(ParseOptions::FULL)
+        .load_file("./xml/data1.xml")
+        .unwrap();
+    let node = document.node();
+    assert_eq!(*node.node_type(), NodeType::Document);
+    let comment = node.first_child().unwrap();
+    assert_eq!(comment.value(), " this is a comment ");
+    let declaration = comment.next_sibling().unwrap();
+    assert_eq!(*declaration.node_type(), NodeType::Declaration);
+    assert_eq!(document.encoding(), Some("UTF-8"));
+    let catalog = document.document_element().unwrap();
+    assert_eq!(catalog.name(), "CATALOG");
+    assert_eq!(document.root().name(), "CATALOG");
+    assert_eq!(*catalog.parent().unwrap().node_type(), NodeType::Document);
+
+    let contents = "<?pi?><!DOCTYPE a><a/><!-- epilogue --><?end?>";
+    let document = parser::parse_with_options(contents.as_bytes(), ParseOptions::FULL).unwrap();
+    let a = document.document_element().unwrap();
+    assert_eq!(a.previous_sibling().unwrap().name(), "a");
+    assert_eq!(
+        *a.previous_sibling().unwrap().node_type(),
+        NodeType::Doctype
+    );
+    let comment = a.next_sibling().unwrap();
+    assert_eq!(comment.value(), " epilogue ");
+    assert_eq!(comment.next_sibling().unwrap().name(), "end");
+    assert_eq!(document.node().first_child().unwrap().name(), "pi");
+
+    let document = parser::parse_str("<a/><b/>").unwrap();
+    assert_eq!(document.root().name(), "a");
+    let options = ParseOptions::DEFAULT | ParseOptions::STRICT;
+    let error = parser::parse_with_options(b"<a/><b/>", options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::MultipleRoots);
+    assert_eq!(error.offset(), 4);
+}
+
+#[test]
+fn parse_text_outside_root() {
+    for contents in ["junk <a/>", "<a/> junk", "<a/> junk <!-- c -->"].iter() {
+        let document = parser::parse_str(contents).unwrap();
+        assert_eq!(document.root().name(), "a");
+        assert!(document.root().first_child().is_none());
+    }
+
+    let options = ParseOptions::DEFAULT | ParseOptions::STRICT;
+    let error = parser::parse_with_options(b"junk <a/>", options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::TextOutsideRoot);
+    assert_eq!(error.offset(), 0);
+    let error = parser::parse_with_options(b"<a/> junk", options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::TextOutsideRoot);
+    assert_eq!(error.offset(), 5);
+    assert!(parser::parse_with_options(b"\n<a/>\n", options).is_ok());
+}
+
+#[test]
+fn parse_borrows_input() {
+    let contents = "<a x='1' y='&lt;'>text<b>x &amp; y</b></a>";
+    let within = |value: &str| contents.as_bytes().as_ptr_range().contains(&value.as_ptr());
+
+    let document = parser::parse_str(contents).unwrap();
+    let root = document.root();
+    assert!(within(root.name()));
+    assert!(within(root.attribute("x").unwrap()));
+    assert!(!within(root.attribute("y").unwrap()));
+    let text = root.first_child().unwrap();
+    assert!(within(text.value()));
+    let b = text.next_sibling().unwrap();
+    assert!(within(b.name()));
+    assert_eq!(b.first_child().unwrap().value(), "x & y");
+    assert!(!within(b.first_child().unwrap().value()));
+}
+
+#[test]
+fn parse_in_place() {
+    let mut buffer = b"<a y='&lt;\r\n'>x &amp; y<b>&#x41;</b></a>".to_vec();
+    let range = buffer.as_ptr_range();
+    let within = |value: &str| range.contains(&value.as_ptr());
+
+    let document = parser::parse_in_place(&mut buffer).unwrap();
+    let root = document.root();
+    assert_eq!(root.attribute("y"), Some("< "));
+    assert!(within(root.attribute("y").unwrap()));
+    let text = root.first_child().unwrap();
+    assert_eq!(text.value(), "x & y");
+    assert!(within(text.value()));
+    assert_eq!(
+        text.next_sibling().unwrap().first_child().unwrap().value(),
+        "A"
+    );
+    assert!(within(root.name()));
+
+    // so do names, and entity values that fit where their references were
+    let mut buffer = b"<!DOCTYPE a [<!ENTITY e 'x'>]>\r\n<a>&e;&e;</a>".to_vec();
+    let range = buffer.as_ptr_range();
+    let within = |value: &str| range.contains(&value.as_ptr());
+    let document = Parser::new()
+        .options(ParseOptions::FULL)
+        .parse_in_place(&mut buffer)
+        .unwrap();
+    let doctype = document.node().first_child().unwrap();
+    assert_eq!(doctype.name(), "a");
+    assert!(within(doctype.name()));
+    assert_eq!(doctype.value(), "a [<!ENTITY e 'x'>]");
+    let text = document.root().first_child().unwrap();
+    assert_eq!(text.value(), "xx");
+    assert!(within(text.value()));
+
+    // documents come out the same as parsed from a buffer left untouched
+    let documents = [
+        "<!DOCTYPE a [<!ENTITY e 'a long &#x65;ntity value'>]><a x='&e;'>&e; &amp;&e;</a>",
+        "<a>x &lt; <![CDATA[&amp;]]> &gt; y<!-- \r\n --><?pi &amp;\r?></a>",
+        "<a x=' 1\t&#9; 2 ' x='&lt;' xml:space='&#x70;reserve'> \t&lt; </a>",
+        "<a>  x \r\n  y  <b xml:space='preserve'> x  </b></a>",
+    ];
+    let options = [
+        ParseOptions::DEFAULT,
+        ParseOptions::FULL | ParseOptions::WNORM_ATTRIBUTE | ParseOptions::COLLAPSE_PCDATA,
+        (ParseOptions::DEFAULT | ParseOptions::WS_PCDATA) & !ParseOptions::CDATA,
+    ];
+    for contents in documents.iter() {
+        for &options in options.iter() {
+            let mut parser = Parser::new();
+            parser
+                .options(options)
+                .duplicate_attributes(DuplicateAttributes::KeepLast);
+            let mut expected = String::new();
+            writer::write_document(&parser.parse(contents.as_bytes()).unwrap(), &mut expected);
+            let mut buffer = contents.as_bytes().to_vec();
+            let mut written = String::new();
+            writer::write_document(&parser.parse_in_place(&mut buffer).unwrap(), &mut written);
+            assert_eq!(written, expected, "{} {:?}", contents, options);
+        }
+    }
+
+    let strict = ParseOptions::DEFAULT | ParseOptions::STRICT;
+    let contents = "<a x='&lt;'>\n&#0;\r\n<b>&bad;</b></a>";
+    let expected = Parser::new()
+        .options(strict)
+        .parse(contents.as_bytes())
+        .unwrap_err();
+    let mut buffer = contents.as_bytes().to_vec();
+    let error = Parser::new()
+        .options(strict)
+        .parse_in_place(&mut buffer)
+        .unwrap_err();
+    assert_eq!(error.to_string(), expected.to_string());
+}
+
+#[test]
+fn parse_encodings() {
+    let text = "<?xml version='1.0'?><a x='\u{e9}'>caf\u{e9} \u{1F600}</a>";
+    let utf16le: Vec<u8> = "\u{feff}"
+        .chars()
+        .chain(text.chars())
+        .collect::<String>()
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes().to_vec())
+        .collect();
+    let utf16be: Vec<u8> = text
+        .encode_utf16()
+        .flat_map(|unit| unit.to_be_bytes().to_vec())
+        .collect();
+    let utf32le: Vec<u8> = text
+        .chars()
+        .flat_map(|c| (c as u32).to_le_bytes().to_vec())
+        .collect();
+    for contents in [utf16le, utf16be, utf32le].iter() {
+        let document = parser::parse(contents).unwrap();
+        assert_eq!(document.root().attribute("x"), Some("\u{e9}"));
+        assert_eq!(
+            document.root().first_child().unwrap().value(),
+            "caf\u{e9} \u{1F600}"
+        );
+    }
+
+    let latin1 = b"<?xml version='1.0' encoding='ISO-8859-1'?><a>caf\xE9</a>";
+    let document = parser::parse(latin1).unwrap();
+    assert_eq!(document.root().first_child().unwrap().value(), "caf\u{e9}");
+    let document = Parser::new()
+        .encoding(Encoding::Latin1)
+        .parse(b"<a>caf\xE9</a>")
+        .unwrap();
+    assert_eq!(document.root().first_child().unwrap().value(), "caf\u{e9}");
+    let error = Parser::new()
+        .encoding(Encoding::Utf8)
+        .parse(latin1)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidUtf8);
+
+    // errors are located in the input, not in its transcoding
+    let contents: Vec<u8> = "<a>\u{1F600}</b>"
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes().to_vec())
+        .collect();
+    let error = parser::parse(&contents).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::MismatchedCloseTag);
+    assert_eq!((error.line(), error.column(), error.offset()), (1, 5, 10));
+    let error = parser::parse(&contents[..contents.len() - 1]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadEncoding);
+    assert_eq!(error.offset(), 16);
+
+    // encodings that cannot be read are only an error in strict mode
+    let contents = b"<?xml version='1.0' encoding='EBCDIC'?><a/>";
+    assert!(parser::parse(contents).is_ok());
+    let error = parser::parse_with_options(contents, ParseOptions::DEFAULT | ParseOptions::STRICT)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnsupportedEncoding);
+    assert_eq!(error.offset(), 30);
+}
+
+#[test]
+fn document_into_owned() {
+    let document: Document<'static> = {
+        let contents = String::from("<!--c--><a x='1'><b y='2'>t<c/></b><d/></a><?pi?>");
+        parser::parse_with_options(contents.as_bytes(), ParseOptions::FULL)
+            .unwrap()
+            .into_owned()
+    };
+    let mut written = String::new();
+    writer::write_document(&document, &mut written);
+    assert_eq!(
+        written,
+        "<!--c--><a x='1'><b y='2'>t<c></c></b><d></d></a><?pi?>"
+    );
+}
+
+#[test]
+fn parse_comments() {
+    let contents = "<!-- prolog --><a><!-- a < b -->x<!--\r\n--><b/></a><!-- epilogue -->";
+
+    let document = parser::parse_with_options(contents.as_bytes(), ParseOptions::FULL).unwrap();
+    let root = document.root();
+    let comment = root.first_child().unwrap();
+    assert_eq!(*comment.node_type(), NodeType::Comment);
+    assert_eq!(comment.value(), " a < b ");
+    let text = comment.next_sibling().unwrap();
+    assert_eq!(text.value(), "x");
+    assert_eq!(text.next_sibling().unwrap().value(), "\n");
+    assert_eq!(root.last_child().unwrap().name(), "b");
+
+    let document = parser::parse_str(contents).unwrap();
+    let root = document.root();
+    assert_eq!(root.first_child().unwrap().value(), "x");
+    assert_eq!(
+        root.first_child().unwrap().next_sibling().unwrap().name(),
+        "b"
+    );
+}
+
+#[test]
+fn parse_bad_comment() {
+    let contents = "<a><!-- a -- b --></a>";
+    assert!(parser::parse_str(contents).is_ok());
+
+    let strict = ParseOptions::FULL | ParseOptions::STRICT;
+    let error = parser::parse_with_options(contents.as_bytes(), strict).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadComment);
+    assert_eq!(error.offset(), 10);
+
+    let error = parser::parse_str("<!-- a -->").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NoRootElement);
+}
+
+#[test]
+fn parse_cdata() {
+    let contents = "<a>x<![CDATA[ if (a < b && c[0]) &amp; ]]>y<![CDATA[]]]]></a>";
+
+    let document = parser::parse_str(contents).unwrap();
+    let cdata = document
+        .root()
+        .first_child()
+        .unwrap()
+        .next_sibling()
+        .unwrap();
+    assert_eq!(*cdata.node_type(), NodeType::CData);
+    assert_eq!(cdata.value(), " if (a < b && c[0]) &amp; ");
+    assert_eq!(document.root().last_child().unwrap().value(), "]]");
+
+    let document = parser::parse_with_options(contents.as_bytes(), ParseOptions::MINIMAL).unwrap();
+    let text = document.root().first_child().unwrap();
+    assert_eq!(*text.node_type(), NodeType::PcData);
+    assert_eq!(text.value(), "x if (a < b && c[0]) &amp; y]]");
+    assert!(text.next_sibling().is_none());
+
+    let error = parser::parse_str("<a><![CDATA[ ]] ></a>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn parse_processing_instructions() {
+    let contents =
+        "<?xml version='1.0'?><a><?xml-stylesheet href=\"a.css\"?><?empty?>x</a><?after?>";
+
+    let document = parser::parse_with_options(contents.as_bytes(), ParseOptions::FULL).unwrap();
+    let pi = document.root().first_child().unwrap();
+    assert_eq!(*pi.node_type(), NodeType::ProcessingInstruction);
+    assert_eq!(pi.name(), "xml-stylesheet");
+    assert_eq!(pi.value(), "href=\"a.css\"");
+    let empty = pi.next_sibling().unwrap();
+    assert_eq!(empty.name(), "empty");
+    assert_eq!(empty.value(), "");
+    assert_eq!(empty.next_sibling().unwrap().value(), "x");
+
+    let document = parser::parse_str("<a><?pi if (a < b) ?>x</a>").unwrap();
+    assert_eq!(document.root().first_child().unwrap().value(), "x");
+
+    let error = parser::parse_str("<a><? pi?></a>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadPI);
+    assert_eq!(error.offset(), 5);
+}
+
+#[test]
+fn parse_declaration() {
+    let contents = "<?xml version=\"1.0\" encoding='UTF-8' standalone = \"no\" ?><a/>";
+
+    let document = parser::parse_with_options(contents.as_bytes(), ParseOptions::FULL).unwrap();
+    let declaration = document.declaration().unwrap();
+    assert_eq!(*declaration.node_type(), NodeType::Declaration);
+    assert_eq!(declaration.name(), "xml");
+    assert_eq!(document.version(), Some("1.0"));
+    assert_eq!(document.encoding(), Some("UTF-8"));
+    assert_eq!(document.standalone(), Some(false));
+
+    let document = parser::parse_str(contents).unwrap();
+    assert!(document.declaration().is_none());
+
+    let mut document = parser::parse_str("<a/>").unwrap();
+    document.set_encoding("UTF-8");
+    assert_eq!(document.version(), Some("1.0"));
+    assert_eq!(document.encoding(), Some("UTF-8"));
+    assert_eq!(document.standalone(), None);
+}
+
+#[test]
+fn parse_bad_declaration() {
+    let error =
+        parser::parse_with_options(b"<?xml version=1.0?><a/>", ParseOptions::FULL).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadDeclaration);
+    assert_eq!(error.offset(), 14);
+    // without ParseOptions::DECLARATION too, and after the root element
+    let error = parser::parse_str("<?xml version=1.0?><a/>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadDeclaration);
+    assert_eq!(error.offset(), 14);
+    let error = parser::parse_str("<a/><?xml version='1.0' standalone?>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadDeclaration);
+    assert_eq!(error.offset(), 34);
+
+    // data1.xml has a comment before its declaration, which only strict mode rejects
+    let mut parser = Parser::new();
+    let document = parser
+        .options(ParseOptions::FULL)
+        .load_file("./xml/data1.xml")
+        .unwrap();
+    assert_eq!(document.encoding(), Some("UTF-8"));
+
+    let strict = ParseOptions::FULL | ParseOptions::STRICT;
+    match parser.options(strict).load_file("./xml/data1.xml") {
+        Err(LoadError::Parse(error)) => {
+            assert_eq!(error.kind(), ErrorKind::MisplacedDeclaration);
+            assert_eq!(error.line(), 2);
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn parse_doctype() {
+    let contents = "<!DOCTYPE greeting PUBLIC '-//ACME//Greeting' \"greeting.dtd\" [\n  \
+                    <!ELEMENT greeting (#PCDATA)>\n  \
+                    <!-- a ] in a comment -->\n  \
+                    <!ATTLIST greeting lang CDATA \"]>\">\n  \
+                    <?pi ]>?>\n]>\n<greeting>hi</greeting>";
+
+    let document = parser::parse_with_options(contents.as_bytes(), ParseOptions::FULL).unwrap();
+    let doctype = document.doctype().unwrap();
+    assert_eq!(*doctype.node_type(), NodeType::Doctype);
+    assert!(doctype.value().starts_with("greeting PUBLIC"));
+    assert!(doctype.value().ends_with("<?pi ]>?>\n]"));
+    assert_eq!(document.doctype_name(), Some("greeting"));
+    assert_eq!(document.public_id(), Some("-//ACME//Greeting"));
+    assert_eq!(document.system_id(), Some("greeting.dtd"));
+    assert_eq!(document.root().name(), "greeting");
+
+    let document = parser::parse_str(contents).unwrap();
+    assert!(document.doctype().is_none());
+    assert_eq!(document.root().first_child().unwrap().value(), "hi");
+
+    let document = parser::parse_with_options(
+        b"<!DOCTYPE note SYSTEM 'note.dtd'><note/>",
+        ParseOptions::FULL,
+    )
+    .unwrap();
+    assert_eq!(document.public_id(), None);
+    assert_eq!(document.system_id(), Some("note.dtd"));
+}
+
+#[test]
+fn parse_bad_doctype() {
+    let error = parser::parse_str("<!DOCTYPE a PUBLIC 'id'><a/>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadDoctype);
+    assert_eq!(error.offset(), 23);
+
+    let error = parser::parse_str("<a><!DOCTYPE a></a>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BadDoctype);
+    assert_eq!(error.offset(), 3);
+
+    let error = parser::parse_str("<!DOCTYPE a [ <!ENTITY e 'x'> <a/>").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn parse_internal_entities() {
+    let contents = "<!DOCTYPE a [\n  \
+                    <!ENTITY % param 'ignored'>\n  \
+                    <!ENTITY company \"ACME &#x26;amp; Sons\">\n  \
+                    <!ENTITY company 'redeclared'>\n  \
+                    <!ENTITY signature '&company;, &#169; 2018'>\n\
+                    ]><a by='&company;'>&signature;</a>";
+
+    let document = parser::parse_str(contents).unwrap();
+    let root = document.root();
+    assert_eq!(root.attribute("by"), Some("ACME & Sons"));
+    assert_eq!(root.first_child().unwrap().value(), "ACME & Sons, © 2018");
+
+    let document = parser::parse_with_options(contents.as_bytes(), ParseOptions::MINIMAL).unwrap();
+    assert_eq!(
+        document.root().first_child().unwrap().value(),
+        "&signature;"
+    );
+
+    let document = parser::parse_str("<!DOCTYPE a [<!ENTITY b '<b>x</b>'>]><a>&b;</a>").unwrap();
+    let text = document.root().first_child().unwrap();
+    assert_eq!(*text.node_type(), NodeType::PcData);
+    assert_eq!(text.value(), "<b>x</b>");
+}
+
+#[test]
+fn parse_entity_expansion_limits() {
+    let laughs = "<!DOCTYPE lolz [\n\
+                  <!ENTITY lol 'lol'>\n\
+                  <!ENTITY lol1 '&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;'>\n\
+                  <!ENTITY lol2 '&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;'>\n\
+                  <!ENTITY lol3 '&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;'>\n\
+                  <!ENTITY lol4 '&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;'>\n\
+                  <!ENTITY lol5 '&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;'>\n\
+                  <!ENTITY lol6 '&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;'>\n\
+                  <!ENTITY lol7 '&lol6;&lol6;&lol6;&lol6;&lol6;&lol6;&lol6;&lol6;&lol6;&lol6;'>\n\
+                  <!ENTITY lol8 '&lol7;&lol7;&lol7;&lol7;&lol7;&lol7;&lol7;&lol7;&lol7;&lol7;'>\n\
+                  <!ENTITY lol9 '&lol8;&lol8;&lol8;&lol8;&lol8;&lol8;&lol8;&lol8;&lol8;&lol8;'>\n\
+                  ]>\n<lolz>&lol9;</lolz>";
+    let error = parser::parse_str(laughs).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::EntityExpansionLimit);
+    assert_eq!(error.line(), 13);
+    assert_eq!(error.column(), 7);
+
+    let error = Parser::new()
+        .max_entity_expansion(1000)
+        .parse(laughs.replace("&lol9;", "&lol3;").as_bytes())
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::EntityExpansionLimit);
+
+    let contents = laughs.replace("&lol9;", "&lol2;");
+    let document = Parser::new()
+        .max_entity_expansion(1000)
+        .parse(contents.as_bytes())
+        .unwrap();
+    assert_eq!(document.root().first_child().unwrap().value().len(), 300);
+
+    let error = Parser::new()
+        .max_entity_depth(2)
+        .parse(contents.as_bytes())
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::EntityExpansionLimit);
+
+    // the depth of an entity expanded before still counts
+    let chain = "<!DOCTYPE a [<!ENTITY l1 'x'><!ENTITY l2 '&l1;'><!ENTITY l3 '&l2;'>\
+                 <!ENTITY l4 '&l3;'><!ENTITY l5 '&l4;'>]>";
+    let mut parser = Parser::new();
+    parser.max_entity_depth(3);
+    for text in ["&l5;", "&l3;&l5;"].iter() {
+        let contents = format!("{}<a>{}</a>", chain, text);
+        let error = parser.parse(contents.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::EntityExpansionLimit);
+    }
+    let contents = format!("{}<a>&l3;&l1;</a>", chain);
+    let document = parser.parse(contents.as_bytes()).unwrap();
+    assert_eq!(document.root().first_child().unwrap().value(), "xx");
+
+    // recursion is an error of its own, however deep entities may go
+    let recursive = "<!DOCTYPE a [<!ENTITY a 'x&b;'><!ENTITY b '&a;'>]><a>&a;</a>";
+    let error = parser::parse_str(recursive).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::RecursiveEntity);
+    assert_eq!(error.offset(), 53);
+    let error = Parser::new()
+        .max_entity_depth(usize::MAX)
+        .parse(b"<!DOCTYPE a [<!ENTITY r '&r;'>]><a x='&r;'/>")
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::RecursiveEntity);
+}
+
+struct MemoryResolver(HashMap<&'static str, &'static str>);
+
+impl EntityResolver for MemoryResolver {
+    fn resolve(&self, _public_id: Option<&str>, system_id: &str) -> Option<Vec<u8>> {
+        self.0.get(system_id).map(|value| value.as_bytes().to_vec())
+    }
+}
+
+#[test]
+fn parse_external_entities() {
+    let contents = "<!DOCTYPE a SYSTEM 'a.dtd' [\n  \
+                    <!ENTITY secret SYSTEM 'file:///etc/passwd'>\n  \
+                    <!ENTITY logo SYSTEM 'logo.png' NDATA png>\n  \
+                    <!ENTITY sign 'internal'>\n\
+                    ]><a>&secret; &sign; &copy;</a>";
+
+    let document = parser::parse_str(contents).unwrap();
+    assert_eq!(
+        document.root().first_child().unwrap().value(),
+        "&secret; internal &copy;"
+    );
+    let error = parser::parse_with_options(
+        contents.as_bytes(),
+        ParseOptions::DEFAULT | ParseOptions::STRICT,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnresolvedEntity);
+    assert_eq!(error.column(), 6);
+
+    let mut files = HashMap::new();
+    files.insert(
+        "file:///etc/passwd",
+        "\u{feff}<?xml encoding='UTF-8'?>root:x:0:0\r\n&sign;",
+    );
+    files.insert(
+        "a.dtd",
+        "<!ENTITY sign 'external'>\n<!ENTITY copy '&#169; ACME'>\n<!ELEMENT a ANY>",
+    );
+    let document = Parser::new()
+        .entity_resolver(MemoryResolver(files))
+        .parse(contents.as_bytes())
+        .unwrap();
+    assert_eq!(
+        document.root().first_child().unwrap().value(),
+        "root:x:0:0\ninternal internal © ACME"
+    );
+
+    let mut files = HashMap::new();
+    files.insert("a.dtd", "<!ENTITY broken 'x>");
+    let error = Parser::new()
+        .entity_resolver(MemoryResolver(files))
+        .parse(contents.as_bytes())
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(error.offset(), 20);
+
+    let contents = "<!DOCTYPE a [<!ENTITY x SYSTEM 'x.txt'><!ENTITY y 'y&x;'>]>\
+                    <a v='&x;' w='&y;'>&y;</a>";
+    let mut files = HashMap::new();
+    files.insert("x.txt", "secret");
+    let document = Parser::new()
+        .entity_resolver(MemoryResolver(files.clone()))
+        .parse(contents.as_bytes())
+        .unwrap();
+    let root = document.root();
+    assert_eq!(root.attribute("v"), Some("&x;"));
+    assert_eq!(root.attribute("w"), Some("y&x;"));
+    assert_eq!(root.first_child().unwrap().value(), "ysecret");
+    let error = Parser::new()
+        .options(ParseOptions::DEFAULT | ParseOptions::STRICT)
+        .entity_resolver(MemoryResolver(files))
+        .parse(contents.as_bytes())
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::ExternalEntityInAttribute);
+    assert_eq!(error.offset(), 65);
+}
+
+#[test]
+fn parsers_are_send() {
+    let mut files = HashMap::new();
+    files.insert("a.dtd", "<!ENTITY sign 'external'>");
+    let mut parser = Parser::new();
+    parser.entity_resolver(MemoryResolver(files));
+    let mut push_parser = parser.push_parser();
+
+    let value = std::thread::spawn(move || {
+        let mut builder = parser.document_builder();
+        push_parser
+            .feed(b"<!DOCTYPE a SYSTEM 'a.dtd'><a>&sign;</a>", &mut builder)
+            .unwrap();
+        push_parser.finish(&mut builder).unwrap();
+        let document = builder.into_document();
+        let value = document.root().first_child().unwrap().value().to_owned();
+        value
+    })
+    .join()
+    .unwrap();
+    assert_eq!(value, "external");
+}